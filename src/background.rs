@@ -0,0 +1,86 @@
+use crate::im::{self, Light};
+use image::{imageops, Rgb};
+
+/// longest side of the downsampled image used for background estimation
+const ESTIMATE_SIZE: u32 = 64;
+/// median window radius in downsampled pixels, large enough to cover objects
+const MEDIAN_RADIUS: i32 = 12;
+
+/// estimates smooth background illumination with a large-kernel median,
+/// computed on a downsampled copy and scaled back up to the source size,
+/// `linear` resamples in linear light
+pub fn estimate_background(
+    buffer: &image::ImageBuffer<Rgb<u8>, Vec<u8>>,
+    linear: bool,
+) -> image::ImageBuffer<Rgb<u8>, Vec<u8>> {
+    if linear {
+        im::buffer_from_linear(&estimate(&im::buffer_into_linear(buffer)))
+    } else {
+        estimate(buffer)
+    }
+}
+
+fn estimate<P: Light + 'static>(
+    buffer: &image::ImageBuffer<Rgb<P>, Vec<P>>,
+) -> image::ImageBuffer<Rgb<P>, Vec<P>>
+where
+    Rgb<P>: image::Pixel<Subpixel = P>,
+{
+    let (w, h) = buffer.dimensions();
+    let scale = ESTIMATE_SIZE as f32 / w.max(h) as f32;
+    let small_w = ((w as f32 * scale).round() as u32).max(1);
+    let small_h = ((h as f32 * scale).round() as u32).max(1);
+    let small = imageops::resize(buffer, small_w, small_h, imageops::FilterType::Triangle);
+
+    let mut median = small.clone();
+    let mut window: [Vec<P>; 3] = [Vec::new(), Vec::new(), Vec::new()];
+    for (x, y, pixel) in median.enumerate_pixels_mut() {
+        for channel in window.iter_mut() {
+            channel.clear();
+        }
+        for dy in -MEDIAN_RADIUS..=MEDIAN_RADIUS {
+            for dx in -MEDIAN_RADIUS..=MEDIAN_RADIUS {
+                let xs = (x as i32 + dx).clamp(0, small_w as i32 - 1) as u32;
+                let ys = (y as i32 + dy).clamp(0, small_h as i32 - 1) as u32;
+                let src_pixel = small.get_pixel(xs, ys);
+                for (c, channel) in window.iter_mut().enumerate() {
+                    channel.push(src_pixel[c]);
+                }
+            }
+        }
+        for (c, channel) in window.iter_mut().enumerate() {
+            let mid = channel.len() / 2;
+            pixel[c] = *channel.select_nth_unstable_by_key(mid, |&v| v.into()).1;
+        }
+    }
+
+    imageops::resize(&median, w, h, imageops::FilterType::Triangle)
+}
+
+/// flat-field correction: divides out the background and rescales
+/// each channel by its mean background level
+pub fn flatten_background(
+    buffer: &image::ImageBuffer<Rgb<u8>, Vec<u8>>,
+    background: &image::ImageBuffer<Rgb<u8>, Vec<u8>>,
+) -> image::ImageBuffer<Rgb<u8>, Vec<u8>> {
+    let mut mean: [f32; 3] = [0.0, 0.0, 0.0];
+    for pixel in background.pixels() {
+        for (c, m) in mean.iter_mut().enumerate() {
+            *m += pixel[c] as f32;
+        }
+    }
+    let area = (background.width() * background.height()).max(1) as f32;
+    for m in mean.iter_mut() {
+        *m /= area;
+    }
+
+    let mut flattened = buffer.clone();
+    for (x, y, pixel) in flattened.enumerate_pixels_mut() {
+        let bg_pixel = background.get_pixel(x, y);
+        for c in 0..3 {
+            let bg = (bg_pixel[c] as f32).max(1.0);
+            pixel[c] = (pixel[c] as f32 / bg * mean[c]).round().clamp(0.0, 255.0) as u8;
+        }
+    }
+    flattened
+}
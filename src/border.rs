@@ -0,0 +1,99 @@
+use crate::im::Light;
+use image::Rgb;
+
+/// how neighbourhood filters treat pixels outside the image
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum BorderMode {
+    /// repeat the nearest edge pixel, `aaa|abcd|ddd`
+    Clamp,
+    /// mirror including the edge pixel, `cba|abcd|dcb`
+    Reflect,
+    /// tile the image, `bcd|abcd|abc`
+    Wrap,
+    /// fixed color outside the image
+    Constant(Rgb<u8>),
+    /// drop outside pixels and average over the remaining window
+    Shrink,
+}
+
+impl BorderMode {
+    pub const NAMES: &'static str = "clamp, reflect, wrap, constant, shrink";
+
+    /// `constant` takes its color from `fill`
+    pub fn from_name(name: &str, fill: Rgb<u8>) -> Option<BorderMode> {
+        let mode = match name {
+            "clamp" => BorderMode::Clamp,
+            "reflect" => BorderMode::Reflect,
+            "wrap" => BorderMode::Wrap,
+            "constant" => BorderMode::Constant(fill),
+            "shrink" => BorderMode::Shrink,
+            _ => return None,
+        };
+        Some(mode)
+    }
+
+    /// source index for a possibly outside `coord` on an axis of `len` pixels,
+    /// `None` when the pixel lies outside for constant and shrink modes
+    pub fn resolve(self, coord: i64, len: u32) -> Option<u32> {
+        let len = len as i64;
+        if (0..len).contains(&coord) {
+            return Some(coord as u32);
+        }
+        match self {
+            BorderMode::Clamp => Some(coord.clamp(0, len - 1) as u32),
+            BorderMode::Reflect => {
+                let period = len * 2;
+                let c = coord.rem_euclid(period);
+                Some(if c < len { c } else { period - 1 - c } as u32)
+            }
+            BorderMode::Wrap => Some(coord.rem_euclid(len) as u32),
+            BorderMode::Constant(_) | BorderMode::Shrink => None,
+        }
+    }
+
+    /// pixel at a possibly outside coordinate, `None` only for shrink mode,
+    /// the constant color is converted from sRGB to the channel type
+    pub fn pixel<P: Light>(
+        self,
+        buffer: &image::ImageBuffer<Rgb<P>, Vec<P>>,
+        x: i64,
+        y: i64,
+    ) -> Option<Rgb<P>>
+    where
+        Rgb<P>: image::Pixel<Subpixel = P>,
+    {
+        match (
+            self.resolve(x, buffer.width()),
+            self.resolve(y, buffer.height()),
+        ) {
+            (Some(xs), Some(ys)) => Some(*buffer.get_pixel(xs, ys)),
+            _ => match self {
+                BorderMode::Constant(color) => Some(Rgb(color.0.map(P::from_srgb))),
+                _ => None,
+            },
+        }
+    }
+}
+
+/// copy of `buffer` extended on each side using `mode`, shrink mode
+/// cannot be padded and returns an unpadded copy
+pub fn pad<P: Light>(
+    buffer: &image::ImageBuffer<Rgb<P>, Vec<P>>,
+    left: u32,
+    top: u32,
+    right: u32,
+    bottom: u32,
+    mode: BorderMode,
+) -> image::ImageBuffer<Rgb<P>, Vec<P>>
+where
+    Rgb<P>: image::Pixel<Subpixel = P>,
+{
+    if let BorderMode::Shrink = mode {
+        return buffer.clone();
+    }
+    let (w, h) = buffer.dimensions();
+    image::ImageBuffer::from_fn(left + w + right, top + h + bottom, |x, y| {
+        mode.pixel(buffer, x as i64 - left as i64, y as i64 - top as i64)
+            .expect("non shrink border always yields a pixel")
+    })
+}
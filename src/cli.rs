@@ -0,0 +1,868 @@
+use crate::{
+    border, convolve, dither, evaluate, geometry, histogram, im, metrics, quantize, smooth, task_1,
+    task_2, task_3,
+};
+use clap::builder::styling::{AnsiColor, Styles};
+use clap::builder::{PossibleValuesParser, TypedValueParser};
+use clap::error::ErrorKind;
+use clap::{Args, CommandFactory, Parser, Subcommand};
+use image::{Rgb, Rgba};
+use std::path::{Path, PathBuf};
+
+// aliases keep clap from treating the lists returned by `from_name` as
+// repeated arguments
+type Orthogonals = Vec<geometry::Orthogonal>;
+type PaletteMethods = Vec<quantize::PaletteMethod>;
+type SmoothFilters = Vec<smooth::SmoothFilter>;
+
+const STYLES: Styles = Styles::styled()
+    .header(AnsiColor::Green.on_default().bold())
+    .usage(AnsiColor::Green.on_default().bold())
+    .literal(AnsiColor::Cyan.on_default().bold())
+    .placeholder(AnsiColor::Cyan.on_default());
+
+/// Image analysis and processing algorithms
+#[derive(Parser)]
+#[command(name = "image_alg", version, styles = STYLES, arg_required_else_help = true)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Command,
+
+    /// Keep images as stored instead of rotating them upright
+    #[arg(long, global = true)]
+    pub no_exif_orientation: bool,
+
+    /// Directory the per image result folders are created in
+    #[arg(
+        long,
+        global = true,
+        value_name = "DIR",
+        default_value = "image_process_results"
+    )]
+    pub out_dir: PathBuf,
+
+    /// Result image format
+    #[arg(long, global = true, value_name = "FORMAT", default_value = "png", value_parser = named(im::OutputFormat::NAMES, im::OutputFormat::from_name))]
+    pub format: im::OutputFormat,
+
+    /// JPEG result quality
+    #[arg(long, global = true, value_name = "1..100", default_value = "90", value_parser = clap::value_parser!(u8).range(1..=100))]
+    pub jpeg_quality: u8,
+
+    /// PNG result compression
+    #[arg(long, global = true, value_name = "LEVEL", default_value = "fast", value_parser = named(im::PngCompression::NAMES, im::PngCompression::from_name))]
+    pub png_compression: im::PngCompression,
+
+    /// PNG result row filter
+    #[arg(long, global = true, value_name = "FILTER", default_value = "adaptive", value_parser = named(im::PngFilter::NAMES, im::PngFilter::from_name))]
+    pub png_filter: im::PngFilter,
+
+    /// Descend into subdirectories of directory paths
+    #[arg(short, long, global = true)]
+    pub recursive: bool,
+
+    /// Only process directory files matching the glob, relative to the directory
+    #[arg(long, global = true, value_name = "GLOB", value_parser = pattern)]
+    pub include: Vec<glob::Pattern>,
+
+    /// Skip directory files and subdirectories matching the glob
+    #[arg(long, global = true, value_name = "GLOB", value_parser = pattern)]
+    pub exclude: Vec<glob::Pattern>,
+
+    /// Images processed at once, 0 uses one worker per CPU core
+    #[arg(short, long, global = true, value_name = "N", default_value = "0")]
+    pub jobs: usize,
+
+    /// Replace or keep result files that already exist
+    #[arg(long, global = true, value_name = "POLICY", default_value = "overwrite", value_parser = named(im::ExistingFiles::NAMES, im::ExistingFiles::from_name))]
+    pub if_exists: im::ExistingFiles,
+}
+
+impl Cli {
+    /// settings shared by every image the command processes
+    pub fn batch(&self) -> im::Batch {
+        im::Batch {
+            input: im::InputOptions {
+                recursive: self.recursive,
+                include: self.include.clone(),
+                exclude: self.exclude.clone(),
+            },
+            output: im::OutputOptions {
+                dir: self.out_dir.clone(),
+                save: im::SaveOptions {
+                    format: self.format,
+                    jpeg_quality: self.jpeg_quality,
+                    png_compression: self.png_compression,
+                    png_filter: self.png_filter,
+                    existing: self.if_exists,
+                },
+            },
+            exif_orientation: !self.no_exif_orientation,
+        }
+    }
+}
+
+/// parses the command line and runs the command, exits with an error
+/// status when it fails
+pub fn main() {
+    let cli = Cli::parse();
+    if cli.jobs > 0 {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(cli.jobs)
+            .build_global()
+            .expect("worker pool");
+    }
+    let batch = cli.batch();
+
+    let result = match cli.command {
+        Command::Task1(args) => {
+            let options = args.options().unwrap_or_else(|err| exit_with_error(err));
+            match task_1::run(&batch, &args.paths, options) {
+                // the scorecard already explains the failed evaluation
+                Ok(false) => std::process::exit(1),
+                result => result.map(|_| ()),
+            }
+        }
+        Command::Task2(args) => task_2::run(&batch, &args.paths, args.width),
+        Command::Task3(args) => {
+            let options = args.options().unwrap_or_else(|err| exit_with_error(err));
+            task_3::run(&batch, &args.paths, &options)
+        }
+        Command::Histogram(args) => histogram::run(
+            &batch,
+            &args.paths,
+            args.grayscale.gray,
+            args.tile,
+            args.clip,
+        ),
+        Command::Rotate(args) => geometry::run_rotate(
+            &batch,
+            &args.paths,
+            args.degrees,
+            args.sampling.interp,
+            args.canvas,
+            args.sampling.fill,
+            args.sampling.linear,
+        ),
+        Command::Orient(args) => geometry::run_orthogonal(&batch, &args.paths, &args.op),
+        Command::Transform(args) => geometry::run_transform(&batch, &args.paths, &args.options()),
+        Command::Quantize(args) => {
+            let dither = args
+                .dither
+                .dither()
+                .unwrap_or_else(|err| exit_with_error(err));
+            quantize::run(
+                &batch,
+                &args.paths,
+                args.colors as usize,
+                &args.palette,
+                &dither,
+            )
+        }
+        Command::Convolve(args) => {
+            let kernel = args.kernel().unwrap_or_else(|err| exit_with_error(err));
+            convolve::run(
+                &batch,
+                &args.paths,
+                &kernel,
+                args.border.mode(),
+                args.linear,
+            )
+        }
+        Command::Smooth(args) => smooth::run(
+            &batch,
+            &args.paths,
+            &args.filter,
+            &args.options(),
+            args.grayscale.gray,
+        ),
+        Command::Compare(args) => metrics::run(
+            &batch,
+            &args.reference,
+            &args.processed,
+            args.grayscale.gray,
+        ),
+        Command::Completions { shell } => {
+            clap_complete::generate(
+                shell,
+                &mut Cli::command(),
+                "image_alg",
+                &mut std::io::stdout(),
+            );
+            Ok(())
+        }
+    };
+    if let Err(err) = result {
+        eprintln!("error: {}", err);
+        std::process::exit(1);
+    }
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Analyze object bounds
+    Task1(Task1Args),
+    /// Downsize content aware horizontal
+    Task2(Task2Args),
+    /// Perform block & rotation & 2..256 intensity level transformations
+    Task3(Task3Args),
+    /// Render histograms, equalize and apply CLAHE
+    Histogram(HistogramArgs),
+    /// Rotate clockwise by any angle
+    Rotate(RotateArgs),
+    /// Lossless rotation, flip, transpose or transverse
+    Orient(OrientArgs),
+    /// Apply affine or projective transform
+    ///
+    /// Steps are always composed in this order, whatever order the options
+    /// are given in: --quad correction, --homography or --affine, --scale,
+    /// --shear, then --translate.
+    Transform(TransformArgs),
+    /// Reduce to an indexed palette of 2..256 colors
+    Quantize(QuantizeArgs),
+    /// Convolve with a text or JSON kernel file or a built-in kernel
+    Convolve(ConvolveArgs),
+    /// Edge preserving smoothing compared to box mean
+    Smooth(SmoothArgs),
+    /// MSE, PSNR, SSIM, MS-SSIM, CIEDE2000 and an error heatmap
+    Compare(CompareArgs),
+    /// Print a shell completion script to stdout
+    Completions {
+        #[arg(value_enum)]
+        shell: clap_complete::Shell,
+    },
+}
+
+#[derive(Args)]
+pub struct Task1Args {
+    /// Image files or directories
+    #[arg(required = true)]
+    pub paths: Vec<PathBuf>,
+
+    #[command(flatten)]
+    pub grayscale: GrayArgs,
+
+    /// Evaluate bounds against JSON or CSV annotations
+    #[arg(long, value_name = "FILE", value_parser = existing_file)]
+    pub truth: Option<PathBuf>,
+
+    /// IoU thresholds for precision and recall
+    #[arg(long, value_name = "T1,T2..", value_delimiter = ',', default_value = "0.5,0.75", value_parser = iou_threshold)]
+    pub iou: Vec<f32>,
+
+    /// Exit with failure when F1 at first IoU threshold is lower
+    #[arg(long, value_name = "VALUE", default_value = "0.5", requires = "truth", value_parser = unit_interval)]
+    pub min_f1: f32,
+
+    /// Bounds rendering
+    #[arg(long, value_name = "MODE", default_value = "boxes", value_parser = named(task_1::OverlayMode::NAMES, task_1::OverlayMode::from_name))]
+    pub overlay: task_1::OverlayMode,
+
+    /// Bounds box outline thickness in pixels
+    #[arg(long, value_name = "PIXELS", default_value = "3", value_parser = clap::value_parser!(u32).range(1..))]
+    pub box_thickness: u32,
+
+    /// Bounds box color
+    #[arg(long, value_name = "RRGGBB", default_value = "228b22", value_parser = hex_color)]
+    pub box_color: Rgb<u8>,
+
+    /// Export each bound as a separate image
+    #[arg(long)]
+    pub crop: bool,
+
+    /// Padding around exported crops in pixels
+    #[arg(long, value_name = "PIXELS", default_value = "0", requires = "crop")]
+    pub crop_padding: u32,
+
+    /// Pad exported crops to a square
+    #[arg(long, requires = "crop")]
+    pub crop_square: bool,
+
+    /// Skip bounds narrower or lower than this
+    #[arg(long, value_name = "PIXELS", default_value = "1", requires = "crop")]
+    pub crop_min_size: u32,
+
+    /// Resample the background estimate in linear light instead of sRGB
+    #[arg(long)]
+    pub linear: bool,
+}
+
+impl Task1Args {
+    pub fn options(&self) -> Result<task_1::Options, String> {
+        let evaluation = match &self.truth {
+            Some(path) => Some(evaluate::Evaluation {
+                annotations: evaluate::Annotations::load(path)
+                    .map_err(|err| format!("failed to load annotations: {}", err))?,
+                iou_thresholds: self.iou.clone(),
+                min_f1: self.min_f1,
+            }),
+            None => None,
+        };
+        let overlay = match self.overlay {
+            task_1::OverlayMode::Lines => task_1::BoundsOverlay::Lines,
+            task_1::OverlayMode::Boxes => task_1::BoundsOverlay::Boxes {
+                thickness: self.box_thickness,
+                color: self.box_color,
+            },
+        };
+        Ok(task_1::Options {
+            gray: self.grayscale.gray,
+            overlay,
+            evaluation,
+            crop: self.crop.then_some(task_1::CropOptions {
+                padding: self.crop_padding,
+                square: self.crop_square,
+                min_size: self.crop_min_size,
+            }),
+            linear: self.linear,
+        })
+    }
+}
+
+#[derive(Args)]
+pub struct Task2Args {
+    /// Image files or directories
+    #[arg(required = true)]
+    pub paths: Vec<PathBuf>,
+
+    /// Pixel columns to remove, below the image width
+    #[arg(long, value_name = "PIXELS", value_parser = clap::value_parser!(u32).range(1..))]
+    pub width: u32,
+}
+
+#[derive(Args)]
+pub struct Task3Args {
+    /// Image files or directories
+    #[arg(required = true)]
+    pub paths: Vec<PathBuf>,
+
+    /// Intensity level counts
+    #[arg(long, required = true, value_name = "N,..", value_delimiter = ',', value_parser = clap::value_parser!(u32).range(2..=256))]
+    pub levels: Vec<u32>,
+
+    /// Intensity level bins
+    #[arg(long, value_name = "METHOD", default_value = "uniform", value_parser = named(task_3::LevelScale::NAMES, task_3::LevelScale::from_name))]
+    pub level_bins: task_3::LevelScale,
+
+    /// Region mean block sizes
+    #[arg(long, value_name = "N|WxH,..", value_delimiter = ',', default_value = "3,5,7", value_parser = block_size)]
+    pub regions: Vec<(u32, u32)>,
+
+    #[command(flatten)]
+    pub border: BorderArgs,
+
+    #[command(flatten)]
+    pub dither: DitherArgs,
+
+    /// Average and interpolate in linear light instead of sRGB
+    #[arg(long)]
+    pub linear: bool,
+}
+
+impl Task3Args {
+    pub fn options(&self) -> Result<task_3::Options, String> {
+        Ok(task_3::Options {
+            intensity_levels: self.levels.clone(),
+            level_scale: self.level_bins,
+            border: self.border.mode(),
+            region_sizes: self.regions.clone(),
+            dither: self.dither.dither()?,
+            linear: self.linear,
+        })
+    }
+}
+
+#[derive(Args)]
+pub struct HistogramArgs {
+    /// Image files or directories
+    #[arg(required = true)]
+    pub paths: Vec<PathBuf>,
+
+    #[command(flatten)]
+    pub grayscale: GrayArgs,
+
+    /// CLAHE tile size in pixels
+    #[arg(long, value_name = "PIXELS", default_value = "64", value_parser = clap::value_parser!(u32).range(1..))]
+    pub tile: u32,
+
+    /// CLAHE clip limit relative to mean bin count
+    #[arg(long, value_name = "LIMIT", default_value = "2.0", value_parser = clip_limit)]
+    pub clip: f32,
+}
+
+#[derive(Args)]
+pub struct RotateArgs {
+    /// Image files or directories
+    #[arg(required = true)]
+    pub paths: Vec<PathBuf>,
+
+    /// Clockwise rotation angle
+    #[arg(long, value_name = "DEGREES", allow_negative_numbers = true, value_parser = finite)]
+    pub degrees: f32,
+
+    /// Rotation canvas
+    #[arg(long, value_name = "MODE", default_value = "expand", value_parser = named(geometry::Canvas::NAMES, geometry::Canvas::from_name))]
+    pub canvas: geometry::Canvas,
+
+    #[command(flatten)]
+    pub sampling: SamplingArgs,
+}
+
+#[derive(Args)]
+pub struct OrientArgs {
+    /// Image files or directories
+    #[arg(required = true)]
+    pub paths: Vec<PathBuf>,
+
+    /// Operation to apply
+    #[arg(long, value_name = "OPERATION", value_parser = named(geometry::Orthogonal::NAMES, geometry::Orthogonal::from_name))]
+    pub op: Orthogonals,
+}
+
+#[derive(Args)]
+pub struct TransformArgs {
+    /// Image files or directories
+    #[arg(required = true)]
+    pub paths: Vec<PathBuf>,
+
+    /// Row major 3x3 transform matrix
+    #[arg(long, value_name = "H1,..,H9", allow_hyphen_values = true, value_parser = numbers::<9>)]
+    pub homography: Option<[f64; 9]>,
+
+    /// Row major 2x3 transform matrix
+    #[arg(long, value_name = "A,B,C,D,E,F", allow_hyphen_values = true, conflicts_with = "homography", value_parser = numbers::<6>)]
+    pub affine: Option<[f64; 6]>,
+
+    /// Positive scale, applied after the matrix
+    #[arg(long, value_name = "S|SX,SY", allow_hyphen_values = true, value_parser = scale_pair)]
+    pub scale: Option<(f64, f64)>,
+
+    /// Shear, applied after scale
+    #[arg(long, value_name = "KX,KY", allow_hyphen_values = true, value_parser = pair)]
+    pub shear: Option<(f64, f64)>,
+
+    /// Translation, applied last, visible with --size
+    #[arg(long, value_name = "TX,TY", allow_hyphen_values = true, value_parser = pair)]
+    pub translate: Option<(f64, f64)>,
+
+    /// Document corners from top left clockwise, corrects perspective
+    #[arg(long, value_name = "X1,Y1,..,X4,Y4", allow_hyphen_values = true, value_parser = quad)]
+    pub quad: Option<[(f64, f64); 4]>,
+
+    /// Output size, default fits the transformed image
+    #[arg(long, value_name = "WxH", value_parser = size)]
+    pub size: Option<(u32, u32)>,
+
+    #[command(flatten)]
+    pub sampling: SamplingArgs,
+}
+
+impl TransformArgs {
+    pub fn options(&self) -> geometry::TransformOptions {
+        let mut matrix = match (self.homography, self.affine) {
+            (Some(m), _) => geometry::Matrix3 { m },
+            (None, Some(a)) => geometry::Matrix3::affine(a),
+            (None, None) => geometry::Matrix3::identity(),
+        };
+        if let Some((sx, sy)) = self.scale {
+            matrix = geometry::Matrix3::scale(sx, sy).multiply(&matrix);
+        }
+        if let Some((kx, ky)) = self.shear {
+            matrix = geometry::Matrix3::shear(kx, ky).multiply(&matrix);
+        }
+        if let Some((tx, ty)) = self.translate {
+            matrix = geometry::Matrix3::translate(tx, ty).multiply(&matrix);
+        }
+        geometry::TransformOptions {
+            matrix,
+            quad: self.quad,
+            size: self.size,
+            interpolation: self.sampling.interp,
+            fill: self.sampling.fill,
+            linear: self.sampling.linear,
+        }
+    }
+}
+
+#[derive(Args)]
+pub struct QuantizeArgs {
+    /// Image files or directories
+    #[arg(required = true)]
+    pub paths: Vec<PathBuf>,
+
+    /// Palette size
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u16).range(2..=256))]
+    pub colors: u16,
+
+    /// Palette method
+    #[arg(long, value_name = "METHOD", default_value = "all", value_parser = named(quantize::PaletteMethod::NAMES, quantize::PaletteMethod::from_name))]
+    pub palette: PaletteMethods,
+
+    #[command(flatten)]
+    pub dither: DitherArgs,
+}
+
+#[derive(Args)]
+pub struct ConvolveArgs {
+    /// Image files or directories
+    #[arg(required = true)]
+    pub paths: Vec<PathBuf>,
+
+    /// Kernel file, or a built-in: gaussian, laplacian, log, dog, emboss, sharpen, motion-blur
+    #[arg(long, value_name = "NAME|FILE")]
+    pub kernel: String,
+
+    /// Gaussian, log and dog kernel sigma
+    #[arg(long, value_name = "VALUE", default_value = "1.0", value_parser = positive)]
+    pub sigma: f32,
+
+    /// Motion blur kernel length in pixels
+    #[arg(long, value_name = "PIXELS", default_value = "9", value_parser = clap::value_parser!(u32).range(1..=255))]
+    pub motion_length: u32,
+
+    /// Motion blur direction in degrees
+    #[arg(long, value_name = "DEGREES", default_value = "0", allow_negative_numbers = true, value_parser = finite)]
+    pub motion_angle: f32,
+
+    /// Scale kernel weights to sum to one
+    #[arg(long)]
+    pub normalize: bool,
+
+    /// Added to results, overrides the kernel offset
+    #[arg(long, value_name = "VALUE", allow_negative_numbers = true, value_parser = finite)]
+    pub offset: Option<f32>,
+
+    #[command(flatten)]
+    pub border: BorderArgs,
+
+    /// Average and interpolate in linear light instead of sRGB
+    #[arg(long)]
+    pub linear: bool,
+}
+
+impl ConvolveArgs {
+    pub fn kernel(&self) -> Result<convolve::Kernel, String> {
+        let path = Path::new(&self.kernel);
+        let kernel = if path.is_file() {
+            convolve::Kernel::load(path).map_err(|err| format!("invalid kernel file: {}", err))?
+        } else {
+            convolve::Kernel::builtin(
+                &self.kernel,
+                self.sigma,
+                self.motion_length,
+                self.motion_angle,
+            )
+            .ok_or_else(|| {
+                format!(
+                    "kernel `{}` is neither a file nor one of: {}",
+                    self.kernel,
+                    convolve::Kernel::BUILTIN_NAMES
+                )
+            })?
+        };
+        let kernel = if self.normalize {
+            kernel.normalized()
+        } else {
+            kernel
+        };
+        Ok(match self.offset {
+            Some(offset) => kernel.with_offset(offset),
+            None => kernel,
+        })
+    }
+}
+
+#[derive(Args)]
+pub struct SmoothArgs {
+    /// Image files or directories
+    #[arg(required = true)]
+    pub paths: Vec<PathBuf>,
+
+    /// Smoothing filter
+    #[arg(long, value_name = "FILTER", default_value = "all", value_parser = named(smooth::SmoothFilter::NAMES, smooth::SmoothFilter::from_name))]
+    pub filter: SmoothFilters,
+
+    /// Median, kuwahara and guided window radius in pixels
+    #[arg(long, value_name = "PIXELS", default_value = "2", value_parser = clap::value_parser!(u32).range(1..))]
+    pub radius: u32,
+
+    /// Bilateral spatial sigma
+    #[arg(long, value_name = "VALUE", default_value = "1.0", value_parser = sigma_space)]
+    pub sigma: f32,
+
+    /// Bilateral range sigma
+    #[arg(long, value_name = "VALUE", default_value = "25", value_parser = positive)]
+    pub sigma_range: f32,
+
+    /// Guided filter regularization
+    #[arg(long, value_name = "VALUE", default_value = "0.01", value_parser = positive)]
+    pub guided_eps: f32,
+
+    /// Non-local means strength
+    #[arg(long, value_name = "VALUE", default_value = "10", value_parser = positive)]
+    pub nlm_h: f32,
+
+    /// Non-local means patch radius in pixels
+    #[arg(long, value_name = "PIXELS", default_value = "1", value_parser = clap::value_parser!(u32).range(..=smooth::MAX_PATCH_RADIUS as i64))]
+    pub nlm_patch: u32,
+
+    /// Non-local means search radius in pixels
+    #[arg(long, value_name = "PIXELS", default_value = "5", value_parser = clap::value_parser!(u32).range(1..=smooth::MAX_SEARCH_RADIUS as i64))]
+    pub nlm_search: u32,
+
+    /// Filter in linear light instead of sRGB, the median is unaffected
+    #[arg(long)]
+    pub linear: bool,
+
+    #[command(flatten)]
+    pub grayscale: GrayArgs,
+}
+
+impl SmoothArgs {
+    pub fn options(&self) -> smooth::SmoothOptions {
+        smooth::SmoothOptions {
+            radius: self.radius,
+            sigma_space: self.sigma,
+            sigma_range: self.sigma_range,
+            eps: self.guided_eps,
+            strength: self.nlm_h,
+            patch_radius: self.nlm_patch,
+            search_radius: self.nlm_search,
+            linear: self.linear,
+        }
+    }
+}
+
+#[derive(Args)]
+pub struct CompareArgs {
+    /// Original image file
+    #[arg(value_parser = existing_file)]
+    pub reference: PathBuf,
+
+    /// Processed image file of the same size
+    #[arg(value_parser = existing_file)]
+    pub processed: PathBuf,
+
+    #[command(flatten)]
+    pub grayscale: GrayArgs,
+}
+
+#[derive(Args)]
+pub struct SamplingArgs {
+    /// Interpolation
+    #[arg(long, value_name = "METHOD", default_value = "bilinear", value_parser = named(geometry::Interpolation::NAMES, geometry::Interpolation::from_name))]
+    pub interp: geometry::Interpolation,
+
+    /// Background outside the source
+    #[arg(long, value_name = "RRGGBB|transparent", default_value = "000000", value_parser = fill)]
+    pub fill: Rgba<u8>,
+
+    /// Average and interpolate in linear light instead of sRGB
+    #[arg(long)]
+    pub linear: bool,
+}
+
+#[derive(Args)]
+pub struct GrayArgs {
+    /// Grayscale conversion
+    #[arg(long, value_name = "METHOD", default_value = "bt601", value_parser = named(im::GrayMethod::NAMES, im::GrayMethod::from_name))]
+    pub gray: im::GrayMethod,
+}
+
+#[derive(Args)]
+pub struct BorderArgs {
+    /// Mean and convolution border handling
+    #[arg(long, value_name = "MODE", default_value = "shrink", value_parser = named(border::BorderMode::NAMES, |name| border::BorderMode::from_name(name, Rgb([0, 0, 0]))))]
+    pub border: border::BorderMode,
+
+    /// Constant border color
+    #[arg(long, value_name = "RRGGBB", default_value = "000000", value_parser = hex_color)]
+    pub fill: Rgb<u8>,
+}
+
+impl BorderArgs {
+    pub fn mode(&self) -> border::BorderMode {
+        match self.border {
+            border::BorderMode::Constant(_) => border::BorderMode::Constant(self.fill),
+            mode => mode,
+        }
+    }
+}
+
+#[derive(Args)]
+pub struct DitherArgs {
+    /// Dithering of the reduced colors
+    #[arg(long, value_name = "METHOD", default_value = "none", value_parser = PossibleValuesParser::new(dither::Dither::NAMES.split(", ")))]
+    pub dither: String,
+
+    /// Bayer matrix or blue noise mask size, default 8 and 64
+    #[arg(long, value_name = "N", value_parser = dither_size)]
+    pub dither_size: Option<usize>,
+
+    /// Alternate error diffusion scan direction per row
+    #[arg(long)]
+    pub serpentine: bool,
+}
+
+impl DitherArgs {
+    pub fn dither(&self) -> Result<dither::Dither, String> {
+        let size = match (self.dither.as_str(), self.dither_size) {
+            (_, Some(size)) => size,
+            ("blue-noise", None) => 64,
+            (_, None) => 8,
+        };
+        dither::Dither::from_name(&self.dither, size, self.serpentine).ok_or_else(|| {
+            format!(
+                "invalid {} size {}, bayer sizes are powers of two in 2..={}, blue noise sizes in 4..={}",
+                self.dither,
+                size,
+                dither::MAX_BAYER_SIZE,
+                dither::MAX_BLUE_NOISE_SIZE
+            )
+        })
+    }
+}
+
+/// exits the way clap reports its own errors, for checks spanning several arguments
+pub fn exit_with_error(message: String) -> ! {
+    Cli::command()
+        .error(ErrorKind::ValueValidation, message)
+        .exit()
+}
+
+/// one of the comma separated `names`, mapped through `from_name`
+fn named<T: Clone + Send + Sync + 'static>(
+    names: &'static str,
+    from_name: fn(&str) -> Option<T>,
+) -> impl TypedValueParser<Value = T> {
+    PossibleValuesParser::new(names.split(", "))
+        .map(move |name| from_name(&name).expect("possible values have a mapping"))
+}
+
+fn finite(text: &str) -> Result<f32, String> {
+    match text.parse::<f32>() {
+        Ok(value) if value.is_finite() => Ok(value),
+        _ => Err("expected a number".to_string()),
+    }
+}
+
+/// bounded by the largest bayer matrix or blue noise mask
+fn dither_size(text: &str) -> Result<usize, String> {
+    let max = dither::MAX_BAYER_SIZE.max(dither::MAX_BLUE_NOISE_SIZE);
+    match text.parse::<usize>() {
+        Ok(size) if (2..=max).contains(&size) => Ok(size),
+        _ => Err(format!("expected a size in 2..={}", max)),
+    }
+}
+
+fn positive(text: &str) -> Result<f32, String> {
+    match finite(text) {
+        Ok(value) if value > 0.0 => Ok(value),
+        _ => Err("expected a positive number".to_string()),
+    }
+}
+
+fn sigma_space(text: &str) -> Result<f32, String> {
+    match positive(text) {
+        Ok(value) if value <= smooth::MAX_SIGMA_SPACE => Ok(value),
+        _ => Err(format!(
+            "expected a positive number up to {}",
+            smooth::MAX_SIGMA_SPACE
+        )),
+    }
+}
+
+fn unit_interval(text: &str) -> Result<f32, String> {
+    match finite(text) {
+        Ok(value) if (0.0..=1.0).contains(&value) => Ok(value),
+        _ => Err("expected a number in [0, 1] range".to_string()),
+    }
+}
+
+fn iou_threshold(text: &str) -> Result<f32, String> {
+    match finite(text.trim()) {
+        Ok(value) if value > 0.0 && value <= 1.0 => Ok(value),
+        _ => Err("expected thresholds in (0, 1] range".to_string()),
+    }
+}
+
+fn clip_limit(text: &str) -> Result<f32, String> {
+    match finite(text) {
+        Ok(value) if value >= 1.0 => Ok(value),
+        _ => Err("expected a clip limit number >= 1.0".to_string()),
+    }
+}
+
+fn hex_color(text: &str) -> Result<Rgb<u8>, String> {
+    im::parse_hex_color(text).ok_or("expected a hex color like `22aa22`".to_string())
+}
+
+fn fill(text: &str) -> Result<Rgba<u8>, String> {
+    geometry::parse_fill(text)
+        .ok_or("expected a hex color like `000000` or `transparent`".to_string())
+}
+
+fn pattern(text: &str) -> Result<glob::Pattern, String> {
+    glob::Pattern::new(text).map_err(|err| err.to_string())
+}
+
+fn existing_file(text: &str) -> Result<PathBuf, String> {
+    let path = PathBuf::from(text);
+    if path.is_file() {
+        Ok(path)
+    } else {
+        Err("file was not found".to_string())
+    }
+}
+
+/// comma separated list of exactly `N` finite numbers
+fn numbers<const N: usize>(text: &str) -> Result<[f64; N], String> {
+    let numbers: Option<Vec<f64>> = text
+        .split(',')
+        .map(|n| n.trim().parse::<f64>().ok().filter(|n| n.is_finite()))
+        .collect();
+    numbers
+        .and_then(|numbers| numbers.try_into().ok())
+        .ok_or(format!("expected {} comma separated numbers", N))
+}
+
+fn pair(text: &str) -> Result<(f64, f64), String> {
+    numbers::<2>(text).map(|[x, y]| (x, y))
+}
+
+/// `S` for uniform scale or `SX,SY`, a zero or negative scale is singular
+/// or mirrors
+fn scale_pair(text: &str) -> Result<(f64, f64), String> {
+    let (sx, sy) = match numbers::<1>(text) {
+        Ok([s]) => (s, s),
+        Err(_) => pair(text)
+            .map_err(|_| "expected 1 or 2 comma separated positive numbers".to_string())?,
+    };
+    for part in text.split(',') {
+        positive(part.trim())?;
+    }
+    Ok((sx, sy))
+}
+
+fn quad(text: &str) -> Result<[(f64, f64); 4], String> {
+    numbers::<8>(text).map(|p| [(p[0], p[1]), (p[2], p[3]), (p[4], p[5]), (p[6], p[7])])
+}
+
+fn size(text: &str) -> Result<(u32, u32), String> {
+    match text.split_once('x').map(|(w, h)| (w.parse(), h.parse())) {
+        Some((Ok(w), Ok(h))) if w > 0 && h > 0 => Ok((w, h)),
+        _ => Err("expected positive dimensions like `800x600`".to_string()),
+    }
+}
+
+/// `N` or `WxH` positive block size
+fn block_size(text: &str) -> Result<(u32, u32), String> {
+    let (w, h) = text
+        .trim()
+        .split_once('x')
+        .unwrap_or((text.trim(), text.trim()));
+    match (w.parse::<u32>(), h.parse::<u32>()) {
+        (Ok(w), Ok(h)) if w > 0 && h > 0 => Ok((w, h)),
+        _ => Err("expected sizes like `3`, `5x5` or `8x4`".to_string()),
+    }
+}
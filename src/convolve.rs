@@ -0,0 +1,535 @@
+use crate::border::BorderMode;
+use crate::error;
+use crate::im;
+use image::Rgb;
+use std::path::{Path, PathBuf};
+
+type RgbBuffer = image::ImageBuffer<Rgb<u8>, Vec<u8>>;
+
+/// odd sized convolution kernel in row major order
+#[derive(Clone)]
+pub struct Kernel {
+    pub name: String,
+    width: usize,
+    height: usize,
+    weights: Vec<f32>,
+    /// horizontal and vertical factors when the kernel is rank one
+    separable: Option<(Vec<f32>, Vec<f32>)>,
+    /// added to the result, centers signed responses at mid gray
+    offset: f32,
+}
+
+impl Kernel {
+    pub const BUILTIN_NAMES: &'static str =
+        "gaussian, laplacian, log, dog, emboss, sharpen, motion-blur";
+
+    pub fn new(
+        name: &str,
+        width: usize,
+        height: usize,
+        weights: Vec<f32>,
+    ) -> Result<Kernel, String> {
+        if width.is_multiple_of(2) || height.is_multiple_of(2) {
+            return Err(format!("kernel size {width}x{height} must be odd"));
+        }
+        if weights.len() != width * height {
+            return Err(format!(
+                "kernel of {width}x{height} needs {} weights, got {}",
+                width * height,
+                weights.len()
+            ));
+        }
+        if weights.iter().any(|w| !w.is_finite()) {
+            return Err("kernel weights must be finite".to_string());
+        }
+        let separable = factor_rank_one(width, height, &weights);
+        Ok(Kernel {
+            name: name.to_string(),
+            width,
+            height,
+            weights,
+            separable,
+            offset: 0.0,
+        })
+    }
+
+    /// built-in kernel by name, `sigma` sizes gaussian, log and dog kernels,
+    /// `length` and `angle` in degrees shape the motion blur
+    pub fn builtin(name: &str, sigma: f32, length: u32, angle: f32) -> Option<Kernel> {
+        let kernel = match name {
+            "gaussian" => gaussian(sigma),
+            "laplacian" => Kernel::new(
+                "laplacian",
+                3,
+                3,
+                vec![0.0, 1.0, 0.0, 1.0, -4.0, 1.0, 0.0, 1.0, 0.0],
+            )
+            .ok()?
+            .with_offset(128.0),
+            "log" => laplacian_of_gaussian(sigma),
+            "dog" => difference_of_gaussians(sigma),
+            "emboss" => Kernel::new(
+                "emboss",
+                3,
+                3,
+                vec![-2.0, -1.0, 0.0, -1.0, 1.0, 1.0, 0.0, 1.0, 2.0],
+            )
+            .ok()?,
+            "sharpen" => Kernel::new(
+                "sharpen",
+                3,
+                3,
+                vec![0.0, -1.0, 0.0, -1.0, 5.0, -1.0, 0.0, -1.0, 0.0],
+            )
+            .ok()?,
+            "motion-blur" => motion_blur(length, angle),
+            _ => return None,
+        };
+        Some(kernel)
+    }
+
+    /// text file of whitespace or comma separated rows, `#` starts a comment,
+    /// or JSON `[[..], ..]` or `{ "kernel": [[..], ..], "divisor": 1, "offset": 0 }`
+    pub fn load(path: &Path) -> Result<Kernel, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|err| format!("failed to read `{}`: {}", path.to_string_lossy(), err))?;
+        let name = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().to_string())
+            .unwrap_or_else(|| "kernel".to_string());
+        let is_json = path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("json"));
+        if is_json {
+            Kernel::parse_json(&name, &text)
+        } else {
+            Kernel::parse_text(&name, &text)
+        }
+    }
+
+    fn parse_text(name: &str, text: &str) -> Result<Kernel, String> {
+        let mut rows: Vec<Vec<f32>> = Vec::new();
+        for (line_idx, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let row = line
+                .split(|c: char| c == ',' || c.is_whitespace())
+                .filter(|value| !value.is_empty())
+                .map(|value| value.parse::<f32>())
+                .collect::<Result<Vec<f32>, _>>()
+                .map_err(|err| format!("line {}: {}", line_idx + 1, err))?;
+            rows.push(row);
+        }
+        Kernel::from_rows(name, rows)
+    }
+
+    fn parse_json(name: &str, text: &str) -> Result<Kernel, String> {
+        let value: serde_json::Value =
+            serde_json::from_str(text).map_err(|err| format!("invalid json: {}", err))?;
+        let (matrix, divisor, offset) = match &value {
+            serde_json::Value::Object(map) => (
+                map.get("kernel")
+                    .ok_or("json object needs a `kernel` array")?,
+                map.get("divisor").and_then(|v| v.as_f64()).unwrap_or(1.0),
+                map.get("offset").and_then(|v| v.as_f64()).unwrap_or(0.0),
+            ),
+            _ => (&value, 1.0, 0.0),
+        };
+        if divisor == 0.0 {
+            return Err("kernel divisor must not be zero".to_string());
+        }
+        let rows = matrix
+            .as_array()
+            .ok_or("kernel must be an array of rows")?
+            .iter()
+            .map(|row| {
+                row.as_array()
+                    .ok_or(format!("kernel row `{}` must be an array", row))?
+                    .iter()
+                    .map(|v| {
+                        v.as_f64()
+                            .map(|v| (v / divisor) as f32)
+                            .ok_or(format!("kernel weight `{}` must be a number", v))
+                    })
+                    .collect::<Result<Vec<f32>, String>>()
+            })
+            .collect::<Result<Vec<Vec<f32>>, String>>()?;
+        Ok(Kernel::from_rows(name, rows)?.with_offset(offset as f32))
+    }
+
+    fn from_rows(name: &str, rows: Vec<Vec<f32>>) -> Result<Kernel, String> {
+        let width = rows.first().map(|row| row.len()).unwrap_or(0);
+        if width == 0 || rows.iter().any(|row| row.len() != width) {
+            return Err("kernel rows must be non empty and of equal length".to_string());
+        }
+        let height = rows.len();
+        Kernel::new(name, width, height, rows.concat())
+    }
+
+    pub fn with_offset(mut self, offset: f32) -> Kernel {
+        self.offset = offset;
+        self
+    }
+
+    /// scales the weights to sum to one, kernels summing to zero are kept
+    pub fn normalized(mut self) -> Kernel {
+        let sum: f32 = self.weights.iter().sum();
+        if sum.abs() > f32::EPSILON {
+            self.weights.iter_mut().for_each(|w| *w /= sum);
+            if let Some((horizontal, _)) = self.separable.as_mut() {
+                horizontal.iter_mut().for_each(|w| *w /= sum);
+            }
+        }
+        self
+    }
+}
+
+/// `(row, column)` factors with `weights = column * row` when they exist
+fn factor_rank_one(width: usize, height: usize, weights: &[f32]) -> Option<(Vec<f32>, Vec<f32>)> {
+    let (pivot, &pivot_weight) = weights
+        .iter()
+        .enumerate()
+        .max_by(|(_, a), (_, b)| a.abs().total_cmp(&b.abs()))?;
+    if pivot_weight == 0.0 || (width == 1 && height == 1) {
+        return None;
+    }
+    let (px, py) = (pivot % width, pivot / width);
+    let row: Vec<f32> = (0..width)
+        .map(|x| weights[py * width + x] / pivot_weight)
+        .collect();
+    let column: Vec<f32> = (0..height).map(|y| weights[y * width + px]).collect();
+    let tolerance = pivot_weight.abs() * 1e-5;
+    let is_rank_one = (0..height).all(|y| {
+        (0..width).all(|x| (weights[y * width + x] - column[y] * row[x]).abs() <= tolerance)
+    });
+    is_rank_one.then_some((row, column))
+}
+
+/// normalized 1D gaussian of `2 * radius + 1` taps
+pub fn gaussian_weights(sigma: f32, radius: usize) -> Vec<f32> {
+    let weights: Vec<f32> = (0..radius * 2 + 1)
+        .map(|i| {
+            let d = i as f32 - radius as f32;
+            (-(d * d) / (2.0 * sigma * sigma)).exp()
+        })
+        .collect();
+    let sum: f32 = weights.iter().sum();
+    weights.into_iter().map(|w| w / sum).collect()
+}
+
+/// three sigmas, at least one pixel
+pub fn gaussian_radius(sigma: f32) -> usize {
+    ((sigma * 3.0).ceil() as usize).max(1)
+}
+
+pub fn gaussian(sigma: f32) -> Kernel {
+    let line = gaussian_weights(sigma, gaussian_radius(sigma));
+    let size = line.len();
+    let weights = line
+        .iter()
+        .flat_map(|&wy| line.iter().map(move |&wx| wx * wy))
+        .collect();
+    Kernel::new(&format!("gaussian_{sigma}"), size, size, weights)
+        .expect("gaussian kernel has odd size")
+}
+
+/// zero sum Laplacian of Gaussian scaled by `sigma^2`, offset to mid gray
+pub fn laplacian_of_gaussian(sigma: f32) -> Kernel {
+    let radius = ((sigma * 4.0).ceil() as usize).max(1);
+    let size = radius * 2 + 1;
+    let mut weights: Vec<f32> = (0..size * size)
+        .map(|idx| {
+            let dx = (idx % size) as f32 - radius as f32;
+            let dy = (idx / size) as f32 - radius as f32;
+            let r = (dx * dx + dy * dy) / (2.0 * sigma * sigma);
+            -(1.0 - r) * (-r).exp() / (std::f32::consts::PI * sigma * sigma)
+        })
+        .collect();
+    let mean = weights.iter().sum::<f32>() / weights.len() as f32;
+    weights.iter_mut().for_each(|w| *w -= mean);
+    Kernel::new(&format!("log_{sigma}"), size, size, weights)
+        .expect("log kernel has odd size")
+        .with_offset(128.0)
+}
+
+/// Gaussian of `sigma` minus Gaussian of `1.6 * sigma`, offset to mid gray
+pub fn difference_of_gaussians(sigma: f32) -> Kernel {
+    let radius = gaussian_radius(sigma * 1.6);
+    let narrow = gaussian_weights(sigma, radius);
+    let wide = gaussian_weights(sigma * 1.6, radius);
+    let size = radius * 2 + 1;
+    let weights = (0..size * size)
+        .map(|idx| {
+            let (x, y) = (idx % size, idx / size);
+            narrow[x] * narrow[y] - wide[x] * wide[y]
+        })
+        .collect();
+    Kernel::new(&format!("dog_{sigma}"), size, size, weights)
+        .expect("dog kernel has odd size")
+        .with_offset(128.0)
+}
+
+/// antialiased line of `length` pixels through the center at `angle` degrees
+pub fn motion_blur(length: u32, angle: f32) -> Kernel {
+    let size = (length.max(1) | 1) as usize;
+    let half = (length.max(1) as f32 - 1.0) / 2.0;
+    let (sin, cos) = angle.to_radians().sin_cos();
+    let center = (size / 2) as f32;
+    let weights: Vec<f32> = (0..size * size)
+        .map(|idx| {
+            let dx = (idx % size) as f32 - center;
+            let dy = (idx / size) as f32 - center;
+            // distance to the segment of half length `half` along the angle
+            let along = (dx * cos + dy * sin).clamp(-half, half);
+            let (nx, ny) = (dx - along * cos, dy - along * sin);
+            (1.0 - (nx * nx + ny * ny).sqrt()).max(0.0)
+        })
+        .collect();
+    Kernel::new(
+        &format!("motion_blur_{length}_{angle}"),
+        size,
+        size,
+        weights,
+    )
+    .expect("motion blur kernel has odd size")
+    .normalized()
+}
+
+pub fn run(
+    batch: &im::Batch,
+    targets: &[PathBuf],
+    kernel: &Kernel,
+    border: BorderMode,
+    linear: bool,
+) -> error::Result<()> {
+    let results = [format!("convolve_{}", kernel.name)];
+    im::for_each_image(batch, targets, &results, |image, info| {
+        im::image_buffer_save(
+            convolve(&image.into_rgb8(), kernel, border, linear),
+            &info.save_path_concat(&results[0]),
+        )
+    })
+}
+
+/// convolves every channel, rank one kernels run as two 1D passes,
+/// shrink borders rescale the taps inside the image to the full kernel sum,
+/// kernels with negative weights fall back to clamp as rescaling their
+/// partial sums is meaningless, `linear` convolves in linear light
+pub fn convolve(
+    buffer: &RgbBuffer,
+    kernel: &Kernel,
+    border: BorderMode,
+    linear: bool,
+) -> RgbBuffer {
+    let planes = convolve_f32(buffer, kernel, border, linear);
+    let mut output = RgbBuffer::new(buffer.width(), buffer.height());
+    for (pixel, value) in output.pixels_mut().zip(planes) {
+        pixel.0 = value.map(|v| {
+            let v = v + kernel.offset;
+            let v = if linear {
+                im::srgb_encode((v / 255.0).clamp(0.0, 1.0)) * 255.0
+            } else {
+                v
+            };
+            v.round().clamp(0.0, 255.0) as u8
+        });
+    }
+    output
+}
+
+/// unclamped convolution result without the kernel offset, scaled to 0..255
+/// linear light when `linear`
+pub fn convolve_f32(
+    buffer: &RgbBuffer,
+    kernel: &Kernel,
+    border: BorderMode,
+    linear: bool,
+) -> Vec<[f32; 3]> {
+    let (width, height) = (buffer.width() as usize, buffer.height() as usize);
+    let border = match border {
+        BorderMode::Shrink if kernel.weights.iter().any(|&w| w < 0.0) => BorderMode::Clamp,
+        border => border,
+    };
+    let value = |v: u8| {
+        if linear {
+            im::srgb_decode_u8(v) * 255.0
+        } else {
+            v as f32
+        }
+    };
+    let source: Vec<[f32; 3]> = buffer.pixels().map(|p| p.0.map(value)).collect();
+    let constant = match border {
+        BorderMode::Constant(color) => color.0.map(value),
+        _ => [0.0; 3],
+    };
+
+    match &kernel.separable {
+        Some((row, column)) => {
+            let horizontal = convolve_axis(&source, width, height, row, true, border, constant);
+            // outside rows of the horizontal result hold the constant times the row sum
+            let row_sum: f32 = row.iter().sum();
+            let constant = constant.map(|c| c * row_sum);
+            convolve_axis(&horizontal, width, height, column, false, border, constant)
+        }
+        None => {
+            let (rx, ry) = (kernel.width / 2, kernel.height / 2);
+            let total: f32 = kernel.weights.iter().sum();
+            let mut output = vec![[0.0f32; 3]; width * height];
+            for y in 0..height {
+                for x in 0..width {
+                    let mut acc = [0.0f32; 3];
+                    let mut inside = 0.0f32;
+                    for ky in 0..kernel.height {
+                        let sy = border.resolve(y as i64 + ry as i64 - ky as i64, height as u32);
+                        for kx in 0..kernel.width {
+                            let weight = kernel.weights[ky * kernel.width + kx];
+                            let sx = border.resolve(x as i64 + rx as i64 - kx as i64, width as u32);
+                            let value = match (sx, sy) {
+                                (Some(sx), Some(sy)) => source[sy as usize * width + sx as usize],
+                                _ if matches!(border, BorderMode::Shrink) => continue,
+                                _ => constant,
+                            };
+                            inside += weight;
+                            for c in 0..3 {
+                                acc[c] += value[c] * weight;
+                            }
+                        }
+                    }
+                    output[y * width + x] = shrink_rescale(acc, inside, total);
+                }
+            }
+            output
+        }
+    }
+}
+
+/// 1D convolution along rows when `horizontal`, otherwise along columns
+fn convolve_axis(
+    source: &[[f32; 3]],
+    width: usize,
+    height: usize,
+    taps: &[f32],
+    horizontal: bool,
+    border: BorderMode,
+    constant: [f32; 3],
+) -> Vec<[f32; 3]> {
+    let radius = taps.len() / 2;
+    let total: f32 = taps.iter().sum();
+    let len = if horizontal { width } else { height };
+    let mut output = vec![[0.0f32; 3]; source.len()];
+    for y in 0..height {
+        for x in 0..width {
+            let pos = if horizontal { x } else { y };
+            let mut acc = [0.0f32; 3];
+            let mut inside = 0.0f32;
+            for (k, &weight) in taps.iter().enumerate() {
+                let value = match border.resolve(pos as i64 + radius as i64 - k as i64, len as u32)
+                {
+                    Some(s) if horizontal => source[y * width + s as usize],
+                    Some(s) => source[s as usize * width + x],
+                    None if matches!(border, BorderMode::Shrink) => continue,
+                    None => constant,
+                };
+                inside += weight;
+                for c in 0..3 {
+                    acc[c] += value[c] * weight;
+                }
+            }
+            output[y * width + x] = shrink_rescale(acc, inside, total);
+        }
+    }
+    output
+}
+
+/// scales a sum over the taps inside the image to the full kernel weight,
+/// zero sum kernels and windows are left as they are
+fn shrink_rescale(acc: [f32; 3], inside: f32, total: f32) -> [f32; 3] {
+    if inside.abs() > f32::EPSILON && total.abs() > f32::EPSILON && inside != total {
+        acc.map(|v| v * total / inside)
+    } else {
+        acc
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::integral::tests::{test_image, IMAGE_SIZES, MODES};
+
+    fn assert_close(a: &[[f32; 3]], b: &[[f32; 3]], context: &str) {
+        for (a, b) in a.iter().zip(b) {
+            for c in 0..3 {
+                assert!((a[c] - b[c]).abs() < 1e-3, "{context}: {a:?} {b:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn rank_one_passes_match_the_full_kernel() {
+        let blur = gaussian(1.0);
+        assert!(blur.separable.is_some());
+        assert!(Kernel::builtin("sharpen", 1.0, 1, 0.0)
+            .unwrap()
+            .separable
+            .is_none());
+        // a non symmetric rank one kernel, outer product of [1 2 0] and [1 -1 3]
+        let skewed = Kernel::new(
+            "skewed",
+            3,
+            3,
+            vec![1.0, 2.0, 0.0, -1.0, -2.0, 0.0, 3.0, 6.0, 0.0],
+        )
+        .unwrap();
+        assert!(skewed.separable.is_some());
+
+        for kernel in [blur, skewed] {
+            let dense = Kernel {
+                separable: None,
+                ..kernel.clone()
+            };
+            for border in MODES {
+                for (width, height) in IMAGE_SIZES {
+                    let buffer = test_image(width, height);
+                    assert_close(
+                        &convolve_f32(&buffer, &kernel, border, false),
+                        &convolve_f32(&buffer, &dense, border, false),
+                        &format!("{} {width}x{height}", kernel.name),
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn shrink_falls_back_to_clamp_for_negative_weights() {
+        let sharpen = Kernel::builtin("sharpen", 1.0, 1, 0.0).unwrap();
+        let buffer = test_image(7, 5);
+        assert_eq!(
+            convolve_f32(&buffer, &sharpen, BorderMode::Shrink, false),
+            convolve_f32(&buffer, &sharpen, BorderMode::Clamp, false)
+        );
+    }
+
+    #[test]
+    fn parses_text_and_json_kernels() {
+        let text = Kernel::parse_text("text", "# box\n1, 2 3\n\n4 5,6 # row\n7 8 9\n").unwrap();
+        assert_eq!((text.width, text.height), (3, 3));
+        assert_eq!(text.weights, (1..=9).map(|v| v as f32).collect::<Vec<_>>());
+
+        let json = Kernel::parse_json(
+            "json",
+            r#"{ "kernel": [[1, 2, 1]], "divisor": 4, "offset": 10 }"#,
+        )
+        .unwrap();
+        assert_eq!((json.width, json.height), (3, 1));
+        assert_eq!(json.weights, vec![0.25, 0.5, 0.25]);
+        assert_eq!(json.offset, 10.0);
+
+        assert!(Kernel::parse_text("even", "1 2\n3 4\n").is_err());
+        assert!(Kernel::parse_text("ragged", "1 2 3\n4 5\n6 7 8\n").is_err());
+        assert!(Kernel::parse_text("word", "1 x 3\n").is_err());
+        assert!(Kernel::parse_json("zero", r#"{ "kernel": [[1]], "divisor": 0 }"#).is_err());
+    }
+}
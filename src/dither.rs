@@ -0,0 +1,337 @@
+use image::Rgb;
+
+type RgbBuffer = image::ImageBuffer<Rgb<u8>, Vec<u8>>;
+
+/// error diffusion neighbours as `(dx, dy, weight)`, weights are divided by
+/// the divisor, dx is mirrored on right to left rows of serpentine scanning
+pub struct DiffusionKernel {
+    pub name: &'static str,
+    pub divisor: f32,
+    pub taps: &'static [(i32, i32, f32)],
+}
+
+pub const FLOYD_STEINBERG: DiffusionKernel = DiffusionKernel {
+    name: "floyd_steinberg",
+    divisor: 16.0,
+    taps: &[(1, 0, 7.0), (-1, 1, 3.0), (0, 1, 5.0), (1, 1, 1.0)],
+};
+
+/// spreads only 6/8 of the error, keeps more contrast in highlights and shadows
+pub const ATKINSON: DiffusionKernel = DiffusionKernel {
+    name: "atkinson",
+    divisor: 8.0,
+    taps: &[
+        (1, 0, 1.0),
+        (2, 0, 1.0),
+        (-1, 1, 1.0),
+        (0, 1, 1.0),
+        (1, 1, 1.0),
+        (0, 2, 1.0),
+    ],
+};
+
+pub const JARVIS_JUDICE_NINKE: DiffusionKernel = DiffusionKernel {
+    name: "jjn",
+    divisor: 48.0,
+    taps: &[
+        (1, 0, 7.0),
+        (2, 0, 5.0),
+        (-2, 1, 3.0),
+        (-1, 1, 5.0),
+        (0, 1, 7.0),
+        (1, 1, 5.0),
+        (2, 1, 3.0),
+        (-2, 2, 1.0),
+        (-1, 2, 3.0),
+        (0, 2, 5.0),
+        (1, 2, 3.0),
+        (2, 2, 1.0),
+    ],
+};
+
+pub const STUCKI: DiffusionKernel = DiffusionKernel {
+    name: "stucki",
+    divisor: 42.0,
+    taps: &[
+        (1, 0, 8.0),
+        (2, 0, 4.0),
+        (-2, 1, 2.0),
+        (-1, 1, 4.0),
+        (0, 1, 8.0),
+        (1, 1, 4.0),
+        (2, 1, 2.0),
+        (-2, 2, 1.0),
+        (-1, 2, 2.0),
+        (0, 2, 4.0),
+        (1, 2, 2.0),
+        (2, 2, 1.0),
+    ],
+};
+
+/// tiled `size` x `size` thresholds in `0..1`
+pub struct ThresholdMap {
+    pub name: &'static str,
+    size: usize,
+    thresholds: Vec<f32>,
+}
+
+impl ThresholdMap {
+    /// recursive Bayer index matrix, `size` must be a power of two
+    pub fn bayer(size: usize) -> ThresholdMap {
+        assert!(size.is_power_of_two(), "bayer size must be a power of two");
+        let mut matrix = vec![0u32];
+        let mut n = 1;
+        while n < size {
+            let mut next = vec![0u32; n * n * 4];
+            for y in 0..n {
+                for x in 0..n {
+                    let v = matrix[y * n + x] * 4;
+                    next[y * 2 * n + x] = v;
+                    next[y * 2 * n + x + n] = v + 2;
+                    next[(y + n) * 2 * n + x] = v + 3;
+                    next[(y + n) * 2 * n + x + n] = v + 1;
+                }
+            }
+            matrix = next;
+            n *= 2;
+        }
+        ThresholdMap::from_ranks("bayer", size, &matrix)
+    }
+
+    /// blue noise mask generated with Ulichney's void-and-cluster method,
+    /// deterministic for a given `size`, every rank costs a pass over the
+    /// whole mask so generation grows with `size` to the fourth power
+    pub fn blue_noise(size: usize) -> ThresholdMap {
+        let ranks = void_and_cluster(size);
+        ThresholdMap::from_ranks("blue_noise", size, &ranks)
+    }
+
+    fn from_ranks(name: &'static str, size: usize, ranks: &[u32]) -> ThresholdMap {
+        let count = ranks.len() as f32;
+        ThresholdMap {
+            name,
+            size,
+            thresholds: ranks.iter().map(|&r| (r as f32 + 0.5) / count).collect(),
+        }
+    }
+
+    pub fn threshold(&self, x: u32, y: u32) -> f32 {
+        let (x, y) = (x as usize % self.size, y as usize % self.size);
+        self.thresholds[y * self.size + x]
+    }
+}
+
+/// largest blue noise mask, generating it already takes seconds
+pub const MAX_BLUE_NOISE_SIZE: usize = 128;
+
+/// largest bayer matrix, 8 bit images gain nothing from more than 256 levels
+pub const MAX_BAYER_SIZE: usize = 256;
+
+pub enum Dither {
+    None,
+    Diffusion {
+        kernel: &'static DiffusionKernel,
+        serpentine: bool,
+    },
+    Ordered(ThresholdMap),
+}
+
+impl Dither {
+    pub const NAMES: &'static str =
+        "none, floyd-steinberg, atkinson, jjn, stucki, bayer, blue-noise";
+
+    /// `size` is the bayer matrix or blue noise mask size
+    pub fn from_name(name: &str, size: usize, serpentine: bool) -> Option<Dither> {
+        let kernel = match name {
+            "none" => return Some(Dither::None),
+            "bayer" if size.is_power_of_two() && (2..=MAX_BAYER_SIZE).contains(&size) => {
+                return Some(Dither::Ordered(ThresholdMap::bayer(size)))
+            }
+            "blue-noise" if (4..=MAX_BLUE_NOISE_SIZE).contains(&size) => {
+                return Some(Dither::Ordered(ThresholdMap::blue_noise(size)))
+            }
+            "floyd-steinberg" => &FLOYD_STEINBERG,
+            "atkinson" => &ATKINSON,
+            "jjn" => &JARVIS_JUDICE_NINKE,
+            "stucki" => &STUCKI,
+            _ => return None,
+        };
+        Some(Dither::Diffusion { kernel, serpentine })
+    }
+
+    /// output postfix, empty without dithering
+    pub fn postfix(&self) -> String {
+        match self {
+            Dither::None => String::new(),
+            Dither::Diffusion { kernel, .. } => format!("_{}", kernel.name),
+            Dither::Ordered(map) => format!("_{}_{}", map.name, map.size),
+        }
+    }
+
+    /// runs `quantize` on every pixel in row major order and collects its
+    /// first result, the second is the reconstructed color used to measure
+    /// the error, `spread` is the ordered dither amplitude in 0..255 units
+    pub fn apply<T, F>(&self, buffer: &RgbBuffer, spread: f32, mut quantize: F) -> Vec<T>
+    where
+        T: Clone + Default,
+        F: FnMut([f32; 3]) -> (T, [f32; 3]),
+    {
+        let (width, height) = buffer.dimensions();
+        let values = |pixel: &Rgb<u8>| [pixel[0] as f32, pixel[1] as f32, pixel[2] as f32];
+        match self {
+            Dither::None => buffer.pixels().map(|p| quantize(values(p)).0).collect(),
+            Dither::Ordered(map) => buffer
+                .enumerate_pixels()
+                .map(|(x, y, p)| {
+                    let offset = (map.threshold(x, y) - 0.5) * spread;
+                    quantize(values(p).map(|v| v + offset)).0
+                })
+                .collect(),
+            Dither::Diffusion { kernel, serpentine } => {
+                let (w, h) = (width as usize, height as usize);
+                let mut work: Vec<[f32; 3]> = buffer.pixels().map(values).collect();
+                let mut output = vec![T::default(); w * h];
+                for y in 0..h {
+                    let reverse = *serpentine && y % 2 == 1;
+                    for step in 0..w {
+                        let x = if reverse { w - 1 - step } else { step };
+                        let idx = y * w + x;
+                        let value = work[idx].map(|v| v.clamp(0.0, 255.0));
+                        let (result, reconstructed) = quantize(value);
+                        output[idx] = result;
+
+                        let error = [0, 1, 2].map(|c| value[c] - reconstructed[c]);
+                        for &(dx, dy, weight) in kernel.taps {
+                            let dx = if reverse { -dx } else { dx };
+                            let nx = x as i64 + dx as i64;
+                            let ny = y as i64 + dy as i64;
+                            if nx < 0 || nx >= w as i64 || ny >= h as i64 {
+                                continue;
+                            }
+                            let target = &mut work[ny as usize * w + nx as usize];
+                            let share = weight / kernel.divisor;
+                            for c in 0..3 {
+                                target[c] += error[c] * share;
+                            }
+                        }
+                    }
+                }
+                output
+            }
+        }
+    }
+}
+
+const BLUE_NOISE_SIGMA: f32 = 1.5;
+
+/// rank of every cell of a `size` x `size` toroidal mask
+fn void_and_cluster(size: usize) -> Vec<u32> {
+    let count = size * size;
+    // gaussian of the wrapped distance from cell 0
+    let kernel: Vec<f32> = (0..count)
+        .map(|idx| {
+            let (x, y) = (idx % size, idx / size);
+            let dx = x.min(size - x) as f32;
+            let dy = y.min(size - y) as f32;
+            (-(dx * dx + dy * dy) / (2.0 * BLUE_NOISE_SIGMA * BLUE_NOISE_SIGMA)).exp()
+        })
+        .collect();
+    let toggle = |energy: &mut [f32], at: usize, sign: f32| {
+        let (ax, ay) = (at % size, at / size);
+        for (idx, e) in energy.iter_mut().enumerate() {
+            let dx = (idx % size + size - ax) % size;
+            let dy = (idx / size + size - ay) % size;
+            *e += sign * kernel[dy * size + dx];
+        }
+    };
+    let tightest_cluster = |pattern: &[bool], energy: &[f32]| {
+        (0..count)
+            .filter(|&idx| pattern[idx])
+            .max_by(|&a, &b| energy[a].total_cmp(&energy[b]))
+            .expect("pattern has a set cell")
+    };
+    let largest_void = |pattern: &[bool], energy: &[f32]| {
+        (0..count)
+            .filter(|&idx| !pattern[idx])
+            .min_by(|&a, &b| energy[a].total_cmp(&energy[b]))
+            .expect("pattern has an empty cell")
+    };
+
+    // initial pattern of about a tenth of the cells from a fixed lcg
+    let mut pattern = vec![false; count];
+    let mut energy = vec![0.0f32; count];
+    let mut seed: u64 = 0x2545_f491_4f6c_dd1d;
+    let mut ones = 0;
+    while ones < (count / 10).max(1) {
+        seed = seed
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        let idx = (seed >> 33) as usize % count;
+        if !pattern[idx] {
+            pattern[idx] = true;
+            toggle(&mut energy, idx, 1.0);
+            ones += 1;
+        }
+    }
+
+    // move points from clusters into voids until the pattern is stable
+    for _ in 0..count {
+        let cluster = tightest_cluster(&pattern, &energy);
+        pattern[cluster] = false;
+        toggle(&mut energy, cluster, -1.0);
+        let void = largest_void(&pattern, &energy);
+        pattern[void] = true;
+        toggle(&mut energy, void, 1.0);
+        if void == cluster {
+            break;
+        }
+    }
+
+    let mut ranks = vec![0u32; count];
+    let prototype = (pattern.clone(), energy.clone());
+    for rank in (0..ones).rev() {
+        let cluster = tightest_cluster(&pattern, &energy);
+        pattern[cluster] = false;
+        toggle(&mut energy, cluster, -1.0);
+        ranks[cluster] = rank as u32;
+    }
+
+    let (mut pattern, mut energy) = prototype;
+    for rank in ones..count {
+        let void = largest_void(&pattern, &energy);
+        pattern[void] = true;
+        toggle(&mut energy, void, 1.0);
+        ranks[void] = rank as u32;
+    }
+    ranks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bayer_two_by_two() {
+        let map = ThresholdMap::bayer(2);
+        assert_eq!(map.thresholds, vec![0.125, 0.625, 0.875, 0.375]);
+        assert_eq!(map.threshold(3, 2), map.threshold(1, 0));
+    }
+
+    #[test]
+    fn masks_rank_every_cell_once() {
+        for map in [ThresholdMap::bayer(8), ThresholdMap::blue_noise(8)] {
+            let mut ranks: Vec<f32> = map.thresholds.iter().map(|t| t * 64.0 - 0.5).collect();
+            ranks.sort_by(f32::total_cmp);
+            let expected: Vec<f32> = (0..64).map(|r| r as f32).collect();
+            assert_eq!(ranks, expected, "{}", map.name);
+        }
+    }
+
+    #[test]
+    fn sizes_are_capped() {
+        assert!(Dither::from_name("bayer", MAX_BAYER_SIZE, false).is_some());
+        assert!(Dither::from_name("bayer", MAX_BAYER_SIZE * 2, false).is_none());
+        assert!(Dither::from_name("bayer", 6, false).is_none());
+        assert!(Dither::from_name("blue-noise", MAX_BLUE_NOISE_SIZE + 1, false).is_none());
+    }
+}
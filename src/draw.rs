@@ -0,0 +1,105 @@
+use crate::im::Rect;
+use image::Rgb;
+
+pub const GLYPH_WIDTH: u32 = 3;
+pub const GLYPH_HEIGHT: u32 = 5;
+
+// 3x5 digit glyphs, each row is 3 bits with the leftmost pixel in the high bit
+const DIGIT_GLYPHS: [[u8; GLYPH_HEIGHT as usize]; 10] = [
+    [0b111, 0b101, 0b101, 0b101, 0b111],
+    [0b010, 0b110, 0b010, 0b010, 0b111],
+    [0b111, 0b001, 0b111, 0b100, 0b111],
+    [0b111, 0b001, 0b111, 0b001, 0b111],
+    [0b101, 0b101, 0b111, 0b001, 0b001],
+    [0b111, 0b100, 0b111, 0b001, 0b111],
+    [0b111, 0b100, 0b111, 0b101, 0b111],
+    [0b111, 0b001, 0b010, 0b010, 0b010],
+    [0b111, 0b101, 0b111, 0b101, 0b111],
+    [0b111, 0b101, 0b111, 0b001, 0b111],
+];
+
+type RgbBuffer = image::ImageBuffer<Rgb<u8>, Vec<u8>>;
+
+pub fn fill_rect(buffer: &mut RgbBuffer, rect: Rect, color: Rgb<u8>) {
+    let x1 = (rect.x + rect.width).min(buffer.width());
+    let y1 = (rect.y + rect.height).min(buffer.height());
+    for y in rect.y..y1 {
+        for x in rect.x..x1 {
+            buffer.put_pixel(x, y, color);
+        }
+    }
+}
+
+/// outline drawn inwards from the rect edges
+pub fn draw_rect_outline(buffer: &mut RgbBuffer, rect: Rect, thickness: u32, color: Rgb<u8>) {
+    let t_x = thickness.min(rect.width);
+    let t_y = thickness.min(rect.height);
+    fill_rect(buffer, Rect::new(rect.x, rect.y, rect.width, t_y), color);
+    fill_rect(
+        buffer,
+        Rect::new(rect.x, rect.y + rect.height - t_y, rect.width, t_y),
+        color,
+    );
+    fill_rect(buffer, Rect::new(rect.x, rect.y, t_x, rect.height), color);
+    fill_rect(
+        buffer,
+        Rect::new(rect.x + rect.width - t_x, rect.y, t_x, rect.height),
+        color,
+    );
+}
+
+/// size of `number` rendered with `draw_number` at `scale`
+pub fn number_size(number: usize, scale: u32) -> (u32, u32) {
+    let digits = number.to_string().len() as u32;
+    (
+        (digits * (GLYPH_WIDTH + 1) - 1) * scale,
+        GLYPH_HEIGHT * scale,
+    )
+}
+
+/// renders decimal digits with the top left corner at `x`, `y`
+pub fn draw_number(
+    buffer: &mut RgbBuffer,
+    number: usize,
+    x: u32,
+    y: u32,
+    scale: u32,
+    color: Rgb<u8>,
+) {
+    for (digit_idx, digit) in number.to_string().bytes().enumerate() {
+        let glyph = &DIGIT_GLYPHS[(digit - b'0') as usize];
+        let glyph_x = x + digit_idx as u32 * (GLYPH_WIDTH + 1) * scale;
+        for (row, bits) in glyph.iter().enumerate() {
+            for col in 0..GLYPH_WIDTH {
+                if bits & (1 << (GLYPH_WIDTH - 1 - col)) == 0 {
+                    continue;
+                }
+                let px = glyph_x + col * scale;
+                let py = y + row as u32 * scale;
+                fill_rect(buffer, Rect::new(px, py, scale, scale), color);
+            }
+        }
+    }
+}
+
+/// number label on a filled tag in the top left corner of `rect`
+pub fn draw_label(
+    buffer: &mut RgbBuffer,
+    rect: Rect,
+    number: usize,
+    scale: u32,
+    color: Rgb<u8>,
+    text_color: Rgb<u8>,
+) {
+    let (w, h) = number_size(number, scale);
+    let tag = Rect::new(rect.x, rect.y, w + scale * 2, h + scale * 2);
+    fill_rect(buffer, tag, color);
+    draw_number(
+        buffer,
+        number,
+        rect.x + scale,
+        rect.y + scale,
+        scale,
+        text_color,
+    );
+}
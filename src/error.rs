@@ -0,0 +1,104 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// why an image could not be loaded, processed or saved
+#[derive(Debug)]
+pub enum Error {
+    /// reading or writing a file or directory
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    /// the file is not a decodable image
+    Decode {
+        path: PathBuf,
+        source: image::ImageError,
+    },
+    /// a result could not be encoded to the output format
+    Encode {
+        path: PathBuf,
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+    /// options that do not fit the image they are applied to
+    InvalidParameter(String),
+    /// some images of a batch failed, each one was reported on its own
+    Batch { failed: usize, total: usize },
+}
+
+impl Error {
+    pub fn io(path: &Path, source: std::io::Error) -> Error {
+        Error::Io {
+            path: path.to_path_buf(),
+            source,
+        }
+    }
+
+    /// decoding failures that come from the file system are kept as io
+    pub fn decode(path: &Path, source: image::ImageError) -> Error {
+        match source {
+            image::ImageError::IoError(source) => Error::io(path, source),
+            source => Error::Decode {
+                path: path.to_path_buf(),
+                source,
+            },
+        }
+    }
+
+    pub fn encode(path: &Path, source: impl std::error::Error + Send + Sync + 'static) -> Error {
+        Error::Encode {
+            path: path.to_path_buf(),
+            source: Box::new(source),
+        }
+    }
+
+    /// file the error happened on, if any
+    pub fn path(&self) -> Option<&Path> {
+        match self {
+            Error::Io { path, .. } | Error::Decode { path, .. } | Error::Encode { path, .. } => {
+                Some(path)
+            }
+            Error::InvalidParameter(_) | Error::Batch { .. } => None,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io { path, source } => {
+                write!(f, "io error on `{}`: {}", path.to_string_lossy(), source)
+            }
+            Error::Decode { path, source } => {
+                write!(
+                    f,
+                    "could not decode `{}`: {}",
+                    path.to_string_lossy(),
+                    source
+                )
+            }
+            Error::Encode { path, source } => {
+                write!(
+                    f,
+                    "could not encode `{}`: {}",
+                    path.to_string_lossy(),
+                    source
+                )
+            }
+            Error::InvalidParameter(message) => write!(f, "invalid parameter: {}", message),
+            Error::Batch { failed, total } => write!(f, "{} of {} images failed", failed, total),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io { source, .. } => Some(source),
+            Error::Decode { source, .. } => Some(source),
+            Error::Encode { source, .. } => Some(source.as_ref()),
+            Error::InvalidParameter(_) | Error::Batch { .. } => None,
+        }
+    }
+}
@@ -0,0 +1,322 @@
+use crate::ansi;
+use crate::im::Rect;
+use std::collections::HashMap;
+use std::path::Path;
+
+/// ground truth bounding boxes keyed by image path without its extension,
+/// a bare name matches images of that stem in any directory
+///
+/// JSON: `{ "image": [[x, y, width, height], ...] }` or
+/// `{ "image": [{ "x": 0, "y": 0, "width": 1, "height": 1 }, ...] }`
+/// CSV: `image,x,y,width,height` per line, header line is optional
+pub struct Annotations {
+    boxes: HashMap<String, Vec<Rect>>,
+}
+
+impl Annotations {
+    pub fn load(path: &Path) -> Result<Annotations, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|err| format!("failed to read `{}`: {}", path.to_string_lossy(), err))?;
+        let is_json = path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("json"));
+        if is_json {
+            Annotations::parse_json(&text)
+        } else {
+            Annotations::parse_csv(&text)
+        }
+    }
+
+    /// looks up boxes by the image path relative to the inputs, falling back
+    /// to its file stem
+    pub fn get(&self, relative: &Path) -> Option<&[Rect]> {
+        let stem = relative.file_stem()?.to_string_lossy();
+        self.boxes
+            .get(&strip_extension(&relative.to_string_lossy()))
+            .or_else(|| self.boxes.get(stem.as_ref()))
+            .map(|boxes| boxes.as_slice())
+    }
+
+    fn parse_json(text: &str) -> Result<Annotations, String> {
+        let value: serde_json::Value =
+            serde_json::from_str(text).map_err(|err| format!("invalid json: {}", err))?;
+        let object = value
+            .as_object()
+            .ok_or("json root must be an object of image name to boxes")?;
+
+        let mut boxes = HashMap::new();
+        for (name, list) in object {
+            let list = list
+                .as_array()
+                .ok_or(format!("`{}` boxes must be an array", name))?;
+            let mut rects = Vec::with_capacity(list.len());
+            for item in list {
+                let field = |key: &str, idx: usize| -> Option<u32> {
+                    let v = match item {
+                        serde_json::Value::Array(values) => values.get(idx)?,
+                        serde_json::Value::Object(map) => map.get(key)?,
+                        _ => return None,
+                    };
+                    v.as_f64().map(|v| v.max(0.0).round() as u32)
+                };
+                match (
+                    field("x", 0),
+                    field("y", 1),
+                    field("width", 2),
+                    field("height", 3),
+                ) {
+                    (Some(x), Some(y), Some(w), Some(h)) => rects.push(Rect::new(x, y, w, h)),
+                    _ => return Err(format!("`{}` has malformed box `{}`", name, item)),
+                }
+            }
+            boxes.insert(strip_extension(name), rects);
+        }
+        Ok(Annotations { boxes })
+    }
+
+    fn parse_csv(text: &str) -> Result<Annotations, String> {
+        let mut boxes: HashMap<String, Vec<Rect>> = HashMap::new();
+        let mut first_row = true;
+        for (line_idx, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let is_first_row = std::mem::replace(&mut first_row, false);
+            let cols: Vec<&str> = line.split(',').map(|col| col.trim()).collect();
+            if cols.len() != 5 {
+                return Err(format!(
+                    "line {}: expected `image,x,y,width,height`",
+                    line_idx + 1
+                ));
+            }
+            let numbers: Result<Vec<u32>, _> = cols[1..]
+                .iter()
+                .map(|col| col.parse::<f32>().map(|v| v.max(0.0).round() as u32))
+                .collect();
+            match numbers {
+                Ok(n) => boxes
+                    .entry(strip_extension(cols[0]))
+                    .or_default()
+                    .push(Rect::new(n[0], n[1], n[2], n[3])),
+                // header line, may follow blank and comment lines
+                Err(_) if is_first_row => continue,
+                Err(_) => return Err(format!("line {}: invalid number", line_idx + 1)),
+            }
+        }
+        Ok(Annotations { boxes })
+    }
+}
+
+/// annotation key of an image path, separators are normalized to `/`
+fn strip_extension(name: &str) -> String {
+    let name = name.replace('\\', "/");
+    let name = name.trim_start_matches("./");
+    Path::new(name)
+        .with_extension("")
+        .to_string_lossy()
+        .to_string()
+}
+
+pub struct Evaluation {
+    pub annotations: Annotations,
+    pub iou_thresholds: Vec<f32>,
+    pub min_f1: f32,
+}
+
+pub struct ImageScore {
+    pub name: String,
+    pub truth_count: usize,
+    pub detected_count: usize,
+    /// iou of each one-to-one match, highest first
+    pub matched_ious: Vec<f32>,
+    /// center distance in pixels of each match, same order as `matched_ious`
+    pub center_errors: Vec<f32>,
+}
+
+impl ImageScore {
+    fn true_positives(&self, threshold: f32) -> usize {
+        self.matched_ious
+            .iter()
+            .filter(|&&iou| iou >= threshold)
+            .count()
+    }
+}
+
+/// greedy one-to-one matching of detected boxes to truth boxes by highest iou
+pub fn score_image(name: &str, truth: &[Rect], detected: &[Rect]) -> ImageScore {
+    let mut pairs = Vec::new();
+    for (t_idx, t) in truth.iter().enumerate() {
+        for (d_idx, d) in detected.iter().enumerate() {
+            let iou = t.iou(d);
+            if iou > 0.0 {
+                pairs.push((iou, t_idx, d_idx));
+            }
+        }
+    }
+    pairs.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+    let mut truth_used = vec![false; truth.len()];
+    let mut detected_used = vec![false; detected.len()];
+    let mut matched_ious = Vec::new();
+    let mut center_errors = Vec::new();
+    for (iou, t_idx, d_idx) in pairs {
+        if truth_used[t_idx] || detected_used[d_idx] {
+            continue;
+        }
+        truth_used[t_idx] = true;
+        detected_used[d_idx] = true;
+        let (tx, ty) = truth[t_idx].center();
+        let (dx, dy) = detected[d_idx].center();
+        matched_ious.push(iou);
+        center_errors.push(((tx - dx).powi(2) + (ty - dy).powi(2)).sqrt());
+    }
+
+    ImageScore {
+        name: name.to_string(),
+        truth_count: truth.len(),
+        detected_count: detected.len(),
+        matched_ious,
+        center_errors,
+    }
+}
+
+fn precision_recall_f1(tp: usize, detected: usize, truth: usize) -> (f32, f32, f32) {
+    let precision = if detected == 0 {
+        0.0
+    } else {
+        tp as f32 / detected as f32
+    };
+    let recall = if truth == 0 {
+        0.0
+    } else {
+        tp as f32 / truth as f32
+    };
+    let f1 = if precision + recall == 0.0 {
+        0.0
+    } else {
+        2.0 * precision * recall / (precision + recall)
+    };
+    (precision, recall, f1)
+}
+
+fn mean(values: &[f32]) -> f32 {
+    if values.is_empty() {
+        0.0
+    } else {
+        values.iter().sum::<f32>() / values.len() as f32
+    }
+}
+
+/// prints per image and aggregate scores, returns false when the aggregate
+/// f1 at the first iou threshold is below `min_f1`
+pub fn print_scorecard(scores: &[ImageScore], iou_thresholds: &[f32], min_f1: f32) -> bool {
+    let g = ansi::GREEN_BOLD;
+    let c = ansi::CYAN_BOLD;
+    let red = ansi::RED_BOLD;
+    let r = ansi::RESET;
+
+    let print_row = |label: &str, truth, detected, ious: &[f32], centers: &[f32], tps: &[usize]| {
+        print!(
+            "{c}{label:<32}{r} truth {truth:>3}  detected {detected:>3}  mean iou {:.3}  center err {:>7.1}px",
+            mean(ious),
+            mean(centers),
+        );
+        for (threshold, &tp) in iou_thresholds.iter().zip(tps.iter()) {
+            let (p, rc, _) = precision_recall_f1(tp, detected, truth);
+            print!("  @{threshold:.2} p {p:.2} r {rc:.2}");
+        }
+        println!();
+    };
+
+    println!("\n{g}Scorecard:{r}");
+    for score in scores {
+        let tps: Vec<usize> = iou_thresholds
+            .iter()
+            .map(|&t| score.true_positives(t))
+            .collect();
+        print_row(
+            &score.name,
+            score.truth_count,
+            score.detected_count,
+            &score.matched_ious,
+            &score.center_errors,
+            &tps,
+        );
+    }
+
+    let truth: usize = scores.iter().map(|s| s.truth_count).sum();
+    let detected: usize = scores.iter().map(|s| s.detected_count).sum();
+    let ious: Vec<f32> = scores.iter().flat_map(|s| s.matched_ious.clone()).collect();
+    let centers: Vec<f32> = scores
+        .iter()
+        .flat_map(|s| s.center_errors.clone())
+        .collect();
+    let tps: Vec<usize> = iou_thresholds
+        .iter()
+        .map(|&t| scores.iter().map(|s| s.true_positives(t)).sum())
+        .collect();
+    print_row("total", truth, detected, &ious, &centers, &tps);
+
+    let (_, _, f1) = precision_recall_f1(tps.first().cloned().unwrap_or(0), detected, truth);
+    let passed = f1 >= min_f1;
+    let status = if passed { g } else { red };
+    println!(
+        "{status}f1 @{:.2} = {:.3}, required {:.3}: {}{r}\n",
+        iou_thresholds.first().cloned().unwrap_or(0.0),
+        f1,
+        min_f1,
+        if passed { "passed" } else { "failed" }
+    );
+    passed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_detections_one_to_one_by_iou() {
+        let truth = [Rect::new(0, 0, 10, 10), Rect::new(20, 0, 10, 10)];
+        let detected = [
+            // half of the first truth box, iou 50 / 100
+            Rect::new(0, 0, 10, 5),
+            // overlaps the first truth box by 50 of 150, taken already
+            Rect::new(5, 0, 10, 10),
+            // overlaps the second truth box by 80 of 120
+            Rect::new(22, 0, 10, 10),
+        ];
+        let score = score_image("image", &truth, &detected);
+        assert_eq!(score.matched_ious, vec![2.0 / 3.0, 0.5]);
+        assert_eq!(score.center_errors, vec![2.0, 2.5]);
+        assert_eq!(score.true_positives(0.5), 2);
+        assert_eq!(score.true_positives(0.6), 1);
+
+        let (precision, recall, f1) = precision_recall_f1(2, detected.len(), truth.len());
+        assert_eq!((precision, recall), (2.0 / 3.0, 1.0));
+        assert!((f1 - 0.8).abs() < 1e-6);
+    }
+
+    #[test]
+    fn parses_json_and_csv_annotations() {
+        let json = Annotations::parse_json(
+            r#"{ "dir/a.png": [[1, 2, 3, 4]], "b": [{ "x": 5, "y": 6, "width": 7, "height": 8 }] }"#,
+        )
+        .unwrap();
+        let csv =
+            Annotations::parse_csv("image,x,y,width,height\ndir/a.png,1,2,3,4\nb.jpg,5,6,7,8\n")
+                .unwrap();
+        for annotations in [json, csv] {
+            assert_eq!(
+                annotations.get(Path::new("dir/a.jpeg")),
+                Some(&[Rect::new(1, 2, 3, 4)][..])
+            );
+            assert_eq!(
+                annotations.get(Path::new("other/b.png")),
+                Some(&[Rect::new(5, 6, 7, 8)][..])
+            );
+            assert_eq!(annotations.get(Path::new("c.png")), None);
+        }
+        assert!(Annotations::parse_csv("a,1,2,3\n").is_err());
+    }
+}
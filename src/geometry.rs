@@ -0,0 +1,738 @@
+use crate::error::{self, Error};
+use crate::im;
+use image::metadata::Orientation;
+use image::{DynamicImage, Rgba};
+use std::path::PathBuf;
+
+pub type RgbaBuffer = image::ImageBuffer<Rgba<u8>, Vec<u8>>;
+
+#[derive(Copy, Clone)]
+pub enum Interpolation {
+    Nearest,
+    Bilinear,
+    /// catmull-rom cubic convolution
+    Bicubic,
+    /// lanczos with a = 3 window
+    Lanczos,
+}
+
+impl Interpolation {
+    pub const NAMES: &'static str = "nearest, bilinear, bicubic, lanczos";
+
+    pub fn from_name(name: &str) -> Option<Interpolation> {
+        let interpolation = match name {
+            "nearest" => Interpolation::Nearest,
+            "bilinear" => Interpolation::Bilinear,
+            "bicubic" => Interpolation::Bicubic,
+            "lanczos" => Interpolation::Lanczos,
+            _ => return None,
+        };
+        Some(interpolation)
+    }
+
+    fn radius(self) -> i32 {
+        match self {
+            Interpolation::Nearest => 0,
+            Interpolation::Bilinear => 1,
+            Interpolation::Bicubic => 2,
+            Interpolation::Lanczos => 3,
+        }
+    }
+
+    fn weight(self, t: f32) -> f32 {
+        let t = t.abs();
+        match self {
+            Interpolation::Nearest => 1.0,
+            Interpolation::Bilinear => (1.0 - t).max(0.0),
+            Interpolation::Bicubic => {
+                const A: f32 = -0.5;
+                if t <= 1.0 {
+                    (A + 2.0) * t.powi(3) - (A + 3.0) * t.powi(2) + 1.0
+                } else if t < 2.0 {
+                    A * t.powi(3) - 5.0 * A * t.powi(2) + 8.0 * A * t - 4.0 * A
+                } else {
+                    0.0
+                }
+            }
+            Interpolation::Lanczos => {
+                const A: f32 = 3.0;
+                if t == 0.0 {
+                    1.0
+                } else if t < A {
+                    let pt = std::f32::consts::PI * t;
+                    A * pt.sin() * (pt / A).sin() / (pt * pt)
+                } else {
+                    0.0
+                }
+            }
+        }
+    }
+}
+
+#[derive(Copy, Clone)]
+pub enum Canvas {
+    /// output has the source dimensions
+    Keep,
+    /// output is the tight bounding box of the rotated source
+    Expand,
+    /// output is the largest axis aligned rectangle inside the rotated source
+    Crop,
+}
+
+impl Canvas {
+    pub const NAMES: &'static str = "keep, expand, crop";
+
+    pub fn from_name(name: &str) -> Option<Canvas> {
+        let canvas = match name {
+            "keep" => Canvas::Keep,
+            "expand" => Canvas::Expand,
+            "crop" => Canvas::Crop,
+            _ => return None,
+        };
+        Some(canvas)
+    }
+}
+
+/// parses `transparent` or a hex color
+pub fn parse_fill(text: &str) -> Option<Rgba<u8>> {
+    if text == "transparent" {
+        return Some(Rgba([0, 0, 0, 0]));
+    }
+    im::parse_hex_color(text).map(|c| Rgba([c[0], c[1], c[2], 255]))
+}
+
+/// lossless rotations and mirrors of the pixel grid
+#[derive(Copy, Clone)]
+pub enum Orthogonal {
+    Rotate90,
+    Rotate180,
+    Rotate270,
+    FlipHorizontal,
+    FlipVertical,
+    /// mirror over the main diagonal
+    Transpose,
+    /// mirror over the anti diagonal
+    Transverse,
+}
+
+impl Orthogonal {
+    pub const NAMES: &'static str =
+        "rotate90, rotate180, rotate270, flip-h, flip-v, transpose, transverse, all";
+    pub const ALL: [Orthogonal; 7] = [
+        Orthogonal::Rotate90,
+        Orthogonal::Rotate180,
+        Orthogonal::Rotate270,
+        Orthogonal::FlipHorizontal,
+        Orthogonal::FlipVertical,
+        Orthogonal::Transpose,
+        Orthogonal::Transverse,
+    ];
+
+    /// `all` yields every operation
+    pub fn from_name(name: &str) -> Option<Vec<Orthogonal>> {
+        let op = match name {
+            "rotate90" => Orthogonal::Rotate90,
+            "rotate180" => Orthogonal::Rotate180,
+            "rotate270" => Orthogonal::Rotate270,
+            "flip-h" => Orthogonal::FlipHorizontal,
+            "flip-v" => Orthogonal::FlipVertical,
+            "transpose" => Orthogonal::Transpose,
+            "transverse" => Orthogonal::Transverse,
+            "all" => return Some(Orthogonal::ALL.to_vec()),
+            _ => return None,
+        };
+        Some(vec![op])
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Orthogonal::Rotate90 => "rotate90",
+            Orthogonal::Rotate180 => "rotate180",
+            Orthogonal::Rotate270 => "rotate270",
+            Orthogonal::FlipHorizontal => "flip_h",
+            Orthogonal::FlipVertical => "flip_v",
+            Orthogonal::Transpose => "transpose",
+            Orthogonal::Transverse => "transverse",
+        }
+    }
+
+    /// the EXIF orientation transform performing the same operation
+    fn orientation(self) -> Orientation {
+        match self {
+            Orthogonal::Rotate90 => Orientation::Rotate90,
+            Orthogonal::Rotate180 => Orientation::Rotate180,
+            Orthogonal::Rotate270 => Orientation::Rotate270,
+            Orthogonal::FlipHorizontal => Orientation::FlipHorizontal,
+            Orthogonal::FlipVertical => Orientation::FlipVertical,
+            Orthogonal::Transpose => Orientation::Rotate90FlipH,
+            Orthogonal::Transverse => Orientation::Rotate270FlipH,
+        }
+    }
+
+    /// keeps the color type and bit depth
+    pub fn apply(self, image: &DynamicImage) -> DynamicImage {
+        let mut image = image.clone();
+        image.apply_orientation(self.orientation());
+        image
+    }
+}
+
+pub fn run_orthogonal(
+    batch: &im::Batch,
+    targets: &[PathBuf],
+    ops: &[Orthogonal],
+) -> error::Result<()> {
+    let results: Vec<String> = ops.iter().map(|op| op.name().to_string()).collect();
+    im::for_each_image(batch, targets, &results, |image, info| {
+        for &op in ops {
+            im::image_save(&op.apply(&image), &info.save_path_concat(op.name()))?;
+        }
+        Ok(())
+    })
+}
+
+pub fn run_rotate(
+    batch: &im::Batch,
+    targets: &[PathBuf],
+    degrees: f32,
+    interpolation: Interpolation,
+    canvas: Canvas,
+    fill: Rgba<u8>,
+    linear: bool,
+) -> error::Result<()> {
+    let results = [format!("rotate_{degrees}")];
+    im::for_each_image(batch, targets, &results, |image, info| {
+        im::image_buffer_rgba_save(
+            rotate(
+                &image.into_rgba8(),
+                degrees,
+                interpolation,
+                canvas,
+                fill,
+                linear,
+            )?,
+            &info.save_path_concat(&results[0]),
+        )
+    })
+}
+
+/// samples `buffer` at continuous coordinates where pixel centers lie on
+/// integers, taps outside the image take the `fill` color, `linear` blends
+/// the color channels in linear light
+pub fn sample(
+    buffer: &RgbaBuffer,
+    x: f32,
+    y: f32,
+    interpolation: Interpolation,
+    fill: Rgba<u8>,
+    linear: bool,
+) -> Rgba<u8> {
+    let (w, h) = (buffer.width() as i32, buffer.height() as i32);
+    let pixel_at = |xs: i32, ys: i32| -> Rgba<u8> {
+        if xs < 0 || ys < 0 || xs >= w || ys >= h {
+            fill
+        } else {
+            *buffer.get_pixel(xs as u32, ys as u32)
+        }
+    };
+
+    if let Interpolation::Nearest = interpolation {
+        return pixel_at(x.round() as i32, y.round() as i32);
+    }
+
+    // premultiplied accumulation keeps transparent fill from darkening edges
+    let radius = interpolation.radius();
+    let (x0, y0) = (x.floor() as i32, y.floor() as i32);
+    let mut sum = [0.0f32; 4];
+    let mut weight_sum = 0.0;
+    for ys in y0 - radius + 1..=y0 + radius {
+        let wy = interpolation.weight(y - ys as f32);
+        if wy == 0.0 {
+            continue;
+        }
+        for xs in x0 - radius + 1..=x0 + radius {
+            let weight = wy * interpolation.weight(x - xs as f32);
+            if weight == 0.0 {
+                continue;
+            }
+            let pixel = pixel_at(xs, ys);
+            let alpha = pixel[3] as f32 / 255.0;
+            for c in 0..3 {
+                let value = if linear {
+                    im::srgb_decode_u8(pixel[c]) * 255.0
+                } else {
+                    pixel[c] as f32
+                };
+                sum[c] += value * alpha * weight;
+            }
+            sum[3] += alpha * weight;
+            weight_sum += weight;
+        }
+    }
+    if weight_sum == 0.0 {
+        return fill;
+    }
+
+    let alpha = sum[3] / weight_sum;
+    if alpha <= 0.0 {
+        return Rgba([0, 0, 0, 0]);
+    }
+    let channel = |v: f32| {
+        let v = v / weight_sum / alpha;
+        let v = if linear {
+            im::srgb_encode((v / 255.0).clamp(0.0, 1.0)) * 255.0
+        } else {
+            v
+        };
+        v.round().clamp(0.0, 255.0) as u8
+    };
+    Rgba([
+        channel(sum[0]),
+        channel(sum[1]),
+        channel(sum[2]),
+        (alpha * 255.0).round().clamp(0.0, 255.0) as u8,
+    ])
+}
+
+/// output dimensions of a `width` x `height` image rotated by `radians`
+pub fn rotated_canvas_dimensions(
+    width: u32,
+    height: u32,
+    radians: f32,
+    canvas: Canvas,
+) -> (u32, u32) {
+    let (w, h) = (width as f32, height as f32);
+    // right angle turns keep whole pixel sizes, the float error of sin and
+    // cos would otherwise add or lose one
+    let snap = |v: f32| {
+        if v < 1e-6 {
+            0.0
+        } else if v > 1.0 - 1e-6 {
+            1.0
+        } else {
+            v
+        }
+    };
+    let (sin, cos) = (snap(radians.sin().abs()), snap(radians.cos().abs()));
+    match canvas {
+        Canvas::Keep => (width, height),
+        Canvas::Expand => (
+            ((w * cos + h * sin) - 1e-3).ceil().max(1.0) as u32,
+            ((w * sin + h * cos) - 1e-3).ceil().max(1.0) as u32,
+        ),
+        Canvas::Crop => {
+            // largest inscribed rectangle, see: https://stackoverflow.com/a/16778797
+            let (long, short) = if w >= h { (w, h) } else { (h, w) };
+            let (cw, ch) = if short <= 2.0 * sin * cos * long || (sin - cos).abs() < 1e-6 {
+                let x = 0.5 * short;
+                if w >= h {
+                    (x / sin, x / cos)
+                } else {
+                    (x / cos, x / sin)
+                }
+            } else {
+                let cos_2a = cos * cos - sin * sin;
+                ((w * cos - h * sin) / cos_2a, (h * cos - w * sin) / cos_2a)
+            };
+            (
+                ((cw + 1e-3).floor() as u32).clamp(1, width.max(height) * 2),
+                ((ch + 1e-3).floor() as u32).clamp(1, width.max(height) * 2),
+            )
+        }
+    }
+}
+
+/// rotates clockwise by `degrees` about the image center
+pub fn rotate(
+    buffer: &RgbaBuffer,
+    degrees: f32,
+    interpolation: Interpolation,
+    canvas: Canvas,
+    fill: Rgba<u8>,
+    linear: bool,
+) -> error::Result<RgbaBuffer> {
+    if !degrees.is_finite() {
+        return Err(Error::InvalidParameter(format!(
+            "rotation angle {} is not finite",
+            degrees
+        )));
+    }
+    let radians = degrees.to_radians();
+    let (out_w, out_h) =
+        rotated_canvas_dimensions(buffer.width(), buffer.height(), radians, canvas);
+
+    let src_cx = (buffer.width() as f64 - 1.0) / 2.0;
+    let src_cy = (buffer.height() as f64 - 1.0) / 2.0;
+    let out_cx = (out_w as f64 - 1.0) / 2.0;
+    let out_cy = (out_h as f64 - 1.0) / 2.0;
+    let matrix = Matrix3::translate(out_cx, out_cy)
+        .multiply(&Matrix3::rotate(radians as f64))
+        .multiply(&Matrix3::translate(-src_cx, -src_cy));
+
+    warp(buffer, &matrix, out_w, out_h, interpolation, fill, linear)
+}
+
+/// row major 3x3 matrix mapping homogeneous source to output coordinates,
+/// affine transforms keep the last row as `0 0 1`
+#[derive(Copy, Clone, Debug)]
+pub struct Matrix3 {
+    pub m: [f64; 9],
+}
+
+impl Matrix3 {
+    pub fn identity() -> Matrix3 {
+        Matrix3 {
+            m: [1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0],
+        }
+    }
+
+    /// `a b c` and `d e f` rows of a 2x3 affine matrix
+    pub fn affine(a: [f64; 6]) -> Matrix3 {
+        Matrix3 {
+            m: [a[0], a[1], a[2], a[3], a[4], a[5], 0.0, 0.0, 1.0],
+        }
+    }
+
+    pub fn translate(tx: f64, ty: f64) -> Matrix3 {
+        Matrix3::affine([1.0, 0.0, tx, 0.0, 1.0, ty])
+    }
+
+    pub fn scale(sx: f64, sy: f64) -> Matrix3 {
+        Matrix3::affine([sx, 0.0, 0.0, 0.0, sy, 0.0])
+    }
+
+    pub fn shear(kx: f64, ky: f64) -> Matrix3 {
+        Matrix3::affine([1.0, kx, 0.0, ky, 1.0, 0.0])
+    }
+
+    /// clockwise rotation in image coordinates with y pointing down
+    pub fn rotate(radians: f64) -> Matrix3 {
+        let (sin, cos) = radians.sin_cos();
+        Matrix3::affine([cos, -sin, 0.0, sin, cos, 0.0])
+    }
+
+    /// `self * other`, so `other` is applied first
+    pub fn multiply(&self, other: &Matrix3) -> Matrix3 {
+        let mut m = [0.0; 9];
+        for row in 0..3 {
+            for col in 0..3 {
+                m[row * 3 + col] = (0..3)
+                    .map(|k| self.m[row * 3 + k] * other.m[k * 3 + col])
+                    .sum();
+            }
+        }
+        Matrix3 { m }
+    }
+
+    pub fn inverse(&self) -> Option<Matrix3> {
+        let m = &self.m;
+        let cofactor = [
+            m[4] * m[8] - m[5] * m[7],
+            m[2] * m[7] - m[1] * m[8],
+            m[1] * m[5] - m[2] * m[4],
+            m[5] * m[6] - m[3] * m[8],
+            m[0] * m[8] - m[2] * m[6],
+            m[2] * m[3] - m[0] * m[5],
+            m[3] * m[7] - m[4] * m[6],
+            m[1] * m[6] - m[0] * m[7],
+            m[0] * m[4] - m[1] * m[3],
+        ];
+        let det = m[0] * cofactor[0] + m[1] * cofactor[3] + m[2] * cofactor[6];
+        if det.abs() < 1e-12 {
+            return None;
+        }
+        Some(Matrix3 {
+            m: cofactor.map(|c| c / det),
+        })
+    }
+
+    /// the same projective transform with its sign chosen so that `(x, y)`
+    /// lies in front of the projection plane, `h` and `-h` map points alike
+    /// but only one of them passes the `w` check of `apply`
+    pub fn facing(&self, x: f64, y: f64) -> Matrix3 {
+        let m = &self.m;
+        if m[6] * x + m[7] * y + m[8] < 0.0 {
+            Matrix3 { m: m.map(|v| -v) }
+        } else {
+            *self
+        }
+    }
+
+    /// maps a point, `None` when it lands on or behind the projection plane
+    pub fn apply(&self, x: f64, y: f64) -> Option<(f64, f64)> {
+        let m = &self.m;
+        let w = m[6] * x + m[7] * y + m[8];
+        if w <= 1e-12 {
+            return None;
+        }
+        Some((
+            (m[0] * x + m[1] * y + m[2]) / w,
+            (m[3] * x + m[4] * y + m[5]) / w,
+        ))
+    }
+
+    /// homography mapping each `src` point onto the matching `dst` point,
+    /// solved as the 8x8 linear system with h33 fixed to 1
+    pub fn from_correspondences(src: &[(f64, f64); 4], dst: &[(f64, f64); 4]) -> Option<Matrix3> {
+        let mut system = [[0.0f64; 9]; 8];
+        for (i, (&(x, y), &(u, v))) in src.iter().zip(dst.iter()).enumerate() {
+            system[i * 2] = [x, y, 1.0, 0.0, 0.0, 0.0, -u * x, -u * y, u];
+            system[i * 2 + 1] = [0.0, 0.0, 0.0, x, y, 1.0, -v * x, -v * y, v];
+        }
+
+        // gaussian elimination with partial pivoting
+        for col in 0..8 {
+            let pivot =
+                (col..8).max_by(|&a, &b| system[a][col].abs().total_cmp(&system[b][col].abs()))?;
+            // nan sorts as the largest pivot and is rejected with singular ones
+            if system[pivot][col].is_nan() || system[pivot][col].abs() < 1e-12 {
+                return None;
+            }
+            system.swap(col, pivot);
+            for row in 0..8 {
+                if row == col {
+                    continue;
+                }
+                let factor = system[row][col] / system[col][col];
+                let pivot_row = system[col];
+                for (value, pivot_value) in system[row].iter_mut().zip(pivot_row).skip(col) {
+                    *value -= factor * pivot_value;
+                }
+            }
+        }
+
+        let mut m = [1.0; 9];
+        for (i, row) in system.iter().enumerate() {
+            m[i] = row[8] / row[i];
+        }
+        Some(Matrix3 { m })
+    }
+}
+
+/// inverse maps every output pixel through `matrix` back into the source,
+/// fails when the matrix is not invertible
+pub fn warp(
+    buffer: &RgbaBuffer,
+    matrix: &Matrix3,
+    out_w: u32,
+    out_h: u32,
+    interpolation: Interpolation,
+    fill: Rgba<u8>,
+    linear: bool,
+) -> error::Result<RgbaBuffer> {
+    let inverse = matrix
+        .inverse()
+        .ok_or_else(|| Error::InvalidParameter("transform matrix is not invertible".to_string()))?;
+    let mut result = RgbaBuffer::new(out_w, out_h);
+    for (x, y, pixel) in result.enumerate_pixels_mut() {
+        *pixel = match inverse.apply(x as f64, y as f64) {
+            Some((sx, sy)) => sample(buffer, sx as f32, sy as f32, interpolation, fill, linear),
+            None => fill,
+        };
+    }
+    Ok(result)
+}
+
+/// bounding box of the transformed source corners as `(min_x, min_y, max_x, max_y)`
+pub fn transformed_bounds(
+    width: u32,
+    height: u32,
+    matrix: &Matrix3,
+) -> Option<(f64, f64, f64, f64)> {
+    let (w, h) = (width as f64 - 1.0, height as f64 - 1.0);
+    let corners = [(0.0, 0.0), (w, 0.0), (0.0, h), (w, h)];
+    let mut bounds = (f64::MAX, f64::MAX, f64::MIN, f64::MIN);
+    for (x, y) in corners {
+        let (tx, ty) = matrix.apply(x, y)?;
+        bounds.0 = bounds.0.min(tx);
+        bounds.1 = bounds.1.min(ty);
+        bounds.2 = bounds.2.max(tx);
+        bounds.3 = bounds.3.max(ty);
+    }
+    Some(bounds)
+}
+
+/// largest output side accepted by `transform`, guards against huge canvases
+pub const MAX_TRANSFORM_SIZE: u32 = 16384;
+
+pub struct TransformOptions {
+    pub matrix: Matrix3,
+    /// source document corners for perspective correction, clockwise from top left
+    pub quad: Option<[(f64, f64); 4]>,
+    /// fixed output size, otherwise the canvas fits the transformed image
+    pub size: Option<(u32, u32)>,
+    pub interpolation: Interpolation,
+    pub fill: Rgba<u8>,
+    /// interpolate in linear light instead of sRGB bytes
+    pub linear: bool,
+}
+
+pub fn run_transform(
+    batch: &im::Batch,
+    targets: &[PathBuf],
+    options: &TransformOptions,
+) -> error::Result<()> {
+    im::for_each_image(batch, targets, &["transform".to_string()], |image, info| {
+        let result = transform(&image.into_rgba8(), options)?;
+        im::image_buffer_rgba_save(result, &info.save_path_concat("transform"))
+    })
+}
+
+pub fn transform(buffer: &RgbaBuffer, options: &TransformOptions) -> error::Result<RgbaBuffer> {
+    let mut matrix = options.matrix;
+    let mut size = options.size;
+
+    // map the quad onto an upright rectangle sized by its longest edges
+    if let Some(quad) = options.quad {
+        let distance =
+            |a: (f64, f64), b: (f64, f64)| ((a.0 - b.0).powi(2) + (a.1 - b.1).powi(2)).sqrt();
+        let w = distance(quad[0], quad[1])
+            .max(distance(quad[3], quad[2]))
+            .round();
+        let h = distance(quad[0], quad[3])
+            .max(distance(quad[1], quad[2]))
+            .round();
+        let rect = [
+            (0.0, 0.0),
+            (w - 1.0, 0.0),
+            (w - 1.0, h - 1.0),
+            (0.0, h - 1.0),
+        ];
+        let homography = Matrix3::from_correspondences(&quad, &rect)
+            .ok_or_else(|| Error::InvalidParameter("quad points are degenerate".to_string()))?;
+        matrix = matrix.multiply(&homography);
+        size = size.or(Some((w as u32, h as u32)));
+    }
+    let (src_cx, src_cy) = (
+        (buffer.width() as f64 - 1.0) / 2.0,
+        (buffer.height() as f64 - 1.0) / 2.0,
+    );
+    matrix = matrix.facing(src_cx, src_cy);
+
+    let (out_w, out_h) = match size {
+        Some(size) => size,
+        None => {
+            let (min_x, min_y, max_x, max_y) =
+                transformed_bounds(buffer.width(), buffer.height(), &matrix).ok_or_else(|| {
+                    Error::InvalidParameter(
+                        "image corners map behind the projection plane, set an output size"
+                            .to_string(),
+                    )
+                })?;
+            matrix = Matrix3::translate(-min_x, -min_y).multiply(&matrix);
+            (
+                (max_x - min_x).round() as u32 + 1,
+                (max_y - min_y).round() as u32 + 1,
+            )
+        }
+    };
+    if out_w == 0 || out_h == 0 || out_w > MAX_TRANSFORM_SIZE || out_h > MAX_TRANSFORM_SIZE {
+        return Err(Error::InvalidParameter(format!(
+            "output size {out_w}x{out_h} is outside 1..={MAX_TRANSFORM_SIZE}"
+        )));
+    }
+
+    warp(
+        buffer,
+        &matrix,
+        out_w,
+        out_h,
+        options.interpolation,
+        options.fill,
+        options.linear,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn right_angle_canvases_are_exact() {
+        for canvas in [Canvas::Keep, Canvas::Expand, Canvas::Crop] {
+            for (degrees, expected) in [
+                (0.0, (717, 478)),
+                (90.0, (478, 717)),
+                (180.0, (717, 478)),
+                (270.0, (478, 717)),
+            ] {
+                let expected = match canvas {
+                    Canvas::Keep => (717, 478),
+                    _ => expected,
+                };
+                let radians = (degrees as f32).to_radians();
+                assert_eq!(
+                    rotated_canvas_dimensions(717, 478, radians, canvas),
+                    expected,
+                    "{} degrees",
+                    degrees
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn diagonal_canvases_of_a_square() {
+        let radians = 45f32.to_radians();
+        // 100 * sqrt(2) = 141.42 and 100 / sqrt(2) = 70.71
+        assert_eq!(
+            rotated_canvas_dimensions(100, 100, radians, Canvas::Expand),
+            (142, 142)
+        );
+        assert_eq!(
+            rotated_canvas_dimensions(100, 100, radians, Canvas::Crop),
+            (70, 70)
+        );
+    }
+
+    #[test]
+    fn homography_round_trips_four_points() {
+        let src = [(10.0, 20.0), (300.0, 35.0), (280.0, 410.0), (15.0, 390.0)];
+        let dst = [(0.0, 0.0), (299.0, 0.0), (299.0, 399.0), (0.0, 399.0)];
+        let homography = Matrix3::from_correspondences(&src, &dst).unwrap();
+        let inverse = homography.inverse().unwrap();
+        for (&(x, y), &(u, v)) in src.iter().zip(dst.iter()) {
+            let (mx, my) = homography.apply(x, y).unwrap();
+            assert!((mx - u).abs() < 1e-6 && (my - v).abs() < 1e-6);
+            let (bx, by) = inverse.apply(u, v).unwrap();
+            assert!((bx - x).abs() < 1e-6 && (by - y).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn collinear_points_have_no_homography() {
+        let src = [(0.0, 0.0), (1.0, 1.0), (2.0, 2.0), (3.0, 3.0)];
+        let dst = [(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)];
+        assert!(Matrix3::from_correspondences(&src, &dst).is_none());
+    }
+
+    #[test]
+    fn warp_moves_pixels_by_the_matrix() {
+        let fill = Rgba([0, 0, 0, 0]);
+        let buffer = RgbaBuffer::from_fn(4, 3, |x, y| Rgba([x as u8 * 50, y as u8 * 80, 0, 255]));
+        let shifted = warp(
+            &buffer,
+            &Matrix3::translate(1.0, 0.0),
+            4,
+            3,
+            Interpolation::Nearest,
+            fill,
+            false,
+        )
+        .unwrap();
+        for (x, y, pixel) in shifted.enumerate_pixels() {
+            let expected = if x == 0 {
+                fill
+            } else {
+                *buffer.get_pixel(x - 1, y)
+            };
+            assert_eq!(*pixel, expected);
+        }
+
+        let flat = Matrix3::scale(0.0, 1.0);
+        assert!(matches!(
+            warp(&buffer, &flat, 4, 3, Interpolation::Nearest, fill, false),
+            Err(Error::InvalidParameter(_))
+        ));
+    }
+}
@@ -0,0 +1,299 @@
+use crate::error;
+use crate::im::{self, GrayMethod};
+use image::Rgb;
+use std::path::PathBuf;
+
+pub const BINS: usize = 256;
+
+const CHART_BIN_WIDTH: u32 = 2;
+const CHART_PANEL_HEIGHT: u32 = 128;
+const CHART_PADDING: u32 = 8;
+
+#[derive(Clone)]
+pub struct Histogram {
+    pub bins: [u32; BINS],
+}
+
+impl Histogram {
+    pub fn from_values(values: impl Iterator<Item = u8>) -> Histogram {
+        let mut bins = [0; BINS];
+        for value in values {
+            bins[value as usize] += 1;
+        }
+        Histogram { bins }
+    }
+
+    pub fn total(&self) -> u32 {
+        self.bins.iter().sum()
+    }
+
+    pub fn cumulative(&self) -> [u32; BINS] {
+        let mut cumulative = [0; BINS];
+        let mut sum = 0;
+        for (c, &count) in cumulative.iter_mut().zip(self.bins.iter()) {
+            sum += count;
+            *c = sum;
+        }
+        cumulative
+    }
+
+    /// mapping that spreads the cumulative distribution over the full range
+    pub fn equalize_mapping(&self) -> [u8; BINS] {
+        let cumulative = self.cumulative();
+        let total = self.total();
+        let cdf_min = cumulative.iter().cloned().find(|&c| c > 0).unwrap_or(0);
+
+        let mut mapping = [0; BINS];
+        if total == cdf_min {
+            for (value, m) in mapping.iter_mut().enumerate() {
+                *m = value as u8;
+            }
+            return mapping;
+        }
+        for (m, &c) in mapping.iter_mut().zip(cumulative.iter()) {
+            let scaled = c.saturating_sub(cdf_min) as f32 / (total - cdf_min) as f32;
+            *m = (scaled * 255.0).round() as u8;
+        }
+        mapping
+    }
+
+    /// clips bins above `limit` and redistributes the excess uniformly
+    pub fn clip(&mut self, limit: u32) {
+        let mut excess: u32 = 0;
+        for bin in self.bins.iter_mut() {
+            if *bin > limit {
+                excess += *bin - limit;
+                *bin = limit;
+            }
+        }
+        let increment = excess / BINS as u32;
+        let remainder = (excess % BINS as u32) as usize;
+        for (idx, bin) in self.bins.iter_mut().enumerate() {
+            *bin += increment;
+            if idx < remainder {
+                *bin += 1;
+            }
+        }
+    }
+}
+
+pub fn run(
+    batch: &im::Batch,
+    targets: &[PathBuf],
+    gray: GrayMethod,
+    tile_size: u32,
+    clip_limit: f32,
+) -> error::Result<()> {
+    let results = [
+        "histogram".to_string(),
+        "equalized".to_string(),
+        "equalized_histogram".to_string(),
+        format!("clahe_{tile_size}_{clip_limit}"),
+        format!("clahe_{tile_size}_{clip_limit}_histogram"),
+    ];
+    im::for_each_image(batch, targets, &results, |image, info| {
+        let buffer = image.into_rgb8();
+
+        let [red, green, blue] = channel_histograms(&buffer);
+        let luma = luma_histogram(&buffer, gray);
+        im::image_buffer_save(
+            render_chart(&[
+                (red, im::COLOR_RED),
+                (green, im::COLOR_GREEN),
+                (blue, COLOR_BLUE),
+                (luma, COLOR_GRAY),
+            ]),
+            &info.save_path_concat("histogram"),
+        )?;
+
+        let equalized = equalize(&buffer, gray);
+        let equalized_luma = luma_histogram(&equalized, gray);
+        im::image_buffer_save(equalized, &info.save_path_concat("equalized"))?;
+        im::image_buffer_save(
+            render_chart(&[(equalized_luma, COLOR_GRAY)]),
+            &info.save_path_concat("equalized_histogram"),
+        )?;
+
+        let clahe = equalize_adaptive(&buffer, gray, tile_size, clip_limit);
+        let clahe_luma = luma_histogram(&clahe, gray);
+        im::image_buffer_save(
+            clahe,
+            &info.save_path_concat(&format!("clahe_{tile_size}_{clip_limit}")),
+        )?;
+        im::image_buffer_save(
+            render_chart(&[(clahe_luma, COLOR_GRAY)]),
+            &info.save_path_concat(&format!("clahe_{tile_size}_{clip_limit}_histogram")),
+        )
+    })
+}
+
+const COLOR_BLUE: Rgb<u8> = Rgb([50, 80, 230]);
+const COLOR_GRAY: Rgb<u8> = Rgb([110, 110, 110]);
+
+pub fn channel_histograms(buffer: &image::ImageBuffer<Rgb<u8>, Vec<u8>>) -> [Histogram; 3] {
+    [0, 1, 2].map(|c| Histogram::from_values(buffer.pixels().map(|pixel| pixel[c])))
+}
+
+pub fn luma_histogram(
+    buffer: &image::ImageBuffer<Rgb<u8>, Vec<u8>>,
+    gray: GrayMethod,
+) -> Histogram {
+    Histogram::from_values(buffer.pixels().map(|&pixel| gray.gray_u8(pixel)))
+}
+
+/// renders stacked histogram panels, bars in panel color with
+/// the normalized cumulative curve drawn over them in black
+pub fn render_chart(panels: &[(Histogram, Rgb<u8>)]) -> image::ImageBuffer<Rgb<u8>, Vec<u8>> {
+    let width = BINS as u32 * CHART_BIN_WIDTH + CHART_PADDING * 2;
+    let height = (CHART_PANEL_HEIGHT + CHART_PADDING) * panels.len() as u32 + CHART_PADDING;
+    let mut chart = image::ImageBuffer::from_pixel(width, height, im::COLOR_WHITE);
+
+    for (panel_idx, (histogram, color)) in panels.iter().enumerate() {
+        let top = CHART_PADDING + panel_idx as u32 * (CHART_PANEL_HEIGHT + CHART_PADDING);
+        let bottom = top + CHART_PANEL_HEIGHT - 1;
+        let max = histogram.bins.iter().cloned().max().unwrap_or(0).max(1);
+        let cumulative = histogram.cumulative();
+        let total = histogram.total().max(1);
+
+        let mut last_curve_y = bottom;
+        for (bin, &count) in histogram.bins.iter().enumerate() {
+            let bar = (count as f32 / max as f32 * (CHART_PANEL_HEIGHT - 1) as f32).round() as u32;
+            let curve = (cumulative[bin] as f32 / total as f32 * (CHART_PANEL_HEIGHT - 1) as f32)
+                .round() as u32;
+            let curve_y = bottom - curve;
+
+            for dx in 0..CHART_BIN_WIDTH {
+                let x = CHART_PADDING + bin as u32 * CHART_BIN_WIDTH + dx;
+                for y in (bottom - bar)..=bottom {
+                    chart.put_pixel(x, y, *color);
+                }
+                // connect to previous curve point so steep segments stay continuous
+                for y in curve_y.min(last_curve_y)..=curve_y.max(last_curve_y) {
+                    chart.put_pixel(x, y, im::COLOR_BLACK);
+                }
+                last_curve_y = curve_y;
+            }
+        }
+    }
+    chart
+}
+
+/// global histogram equalization of the luma, applied to rgb by luma offset
+pub fn equalize(
+    buffer: &image::ImageBuffer<Rgb<u8>, Vec<u8>>,
+    gray: GrayMethod,
+) -> image::ImageBuffer<Rgb<u8>, Vec<u8>> {
+    let mapping = luma_histogram(buffer, gray).equalize_mapping();
+    let mut result = buffer.clone();
+    for pixel in result.pixels_mut() {
+        let luma = gray.gray_u8(*pixel);
+        *pixel = shift_luma(*pixel, luma, mapping[luma as usize]);
+    }
+    result
+}
+
+/// contrast limited adaptive histogram equalization, `tile_size` is in pixels
+/// and `clip_limit` is a multiple of the mean bin count of a tile
+pub fn equalize_adaptive(
+    buffer: &image::ImageBuffer<Rgb<u8>, Vec<u8>>,
+    gray: GrayMethod,
+    tile_size: u32,
+    clip_limit: f32,
+) -> image::ImageBuffer<Rgb<u8>, Vec<u8>> {
+    let (w, h) = buffer.dimensions();
+    let tiles_x = w.div_ceil(tile_size).max(1);
+    let tiles_y = h.div_ceil(tile_size).max(1);
+
+    let mut luma = image::ImageBuffer::<image::Luma<u8>, Vec<u8>>::new(w, h);
+    for (x, y, pixel) in buffer.enumerate_pixels() {
+        luma.put_pixel(x, y, image::Luma([gray.gray_u8(*pixel)]));
+    }
+
+    let mut mappings = Vec::with_capacity((tiles_x * tiles_y) as usize);
+    for ty in 0..tiles_y {
+        for tx in 0..tiles_x {
+            let x0 = tx * tile_size;
+            let y0 = ty * tile_size;
+            let x1 = (x0 + tile_size).min(w);
+            let y1 = (y0 + tile_size).min(h);
+            let mut histogram = Histogram::from_values(
+                (y0..y1)
+                    .flat_map(|y| (x0..x1).map(move |x| (x, y)))
+                    .map(|(x, y)| luma.get_pixel(x, y)[0]),
+            );
+            let area = (x1 - x0) * (y1 - y0);
+            let limit = ((clip_limit * area as f32 / BINS as f32).ceil() as u32).max(1);
+            histogram.clip(limit);
+            mappings.push(histogram.equalize_mapping());
+        }
+    }
+
+    // bilinear interpolation between mappings of the four nearest tile centers
+    let tile_coord = |pos: u32, tiles: u32| -> (usize, usize, f32) {
+        let t = (pos as f32 + 0.5) / tile_size as f32 - 0.5;
+        let t = t.clamp(0.0, (tiles - 1) as f32);
+        let t0 = t.floor() as usize;
+        let t1 = (t0 + 1).min(tiles as usize - 1);
+        (t0, t1, t - t0 as f32)
+    };
+
+    let mut result = buffer.clone();
+    for (x, y, pixel) in result.enumerate_pixels_mut() {
+        let value = luma.get_pixel(x, y)[0] as usize;
+        let (tx0, tx1, fx) = tile_coord(x, tiles_x);
+        let (ty0, ty1, fy) = tile_coord(y, tiles_y);
+        let map = |tx: usize, ty: usize| mappings[ty * tiles_x as usize + tx][value] as f32;
+
+        let top = map(tx0, ty0) * (1.0 - fx) + map(tx1, ty0) * fx;
+        let bottom = map(tx0, ty1) * (1.0 - fx) + map(tx1, ty1) * fx;
+        let mapped = (top * (1.0 - fy) + bottom * fy).round() as u8;
+        *pixel = shift_luma(*pixel, value as u8, mapped);
+    }
+    result
+}
+
+fn shift_luma(pixel: Rgb<u8>, luma: u8, mapped: u8) -> Rgb<u8> {
+    let delta = mapped as i32 - luma as i32;
+    Rgb(pixel.0.map(|c| (c as i32 + delta).clamp(0, 255) as u8))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type RgbBuffer = image::ImageBuffer<Rgb<u8>, Vec<u8>>;
+
+    #[test]
+    fn equalization_spreads_values_to_the_full_range() {
+        let histogram = Histogram::from_values([10, 10, 20, 30].into_iter());
+        let mapping = histogram.equalize_mapping();
+        assert_eq!([mapping[10], mapping[20], mapping[30]], [0, 128, 255]);
+
+        let flat = Histogram::from_values([7; 5].into_iter());
+        assert_eq!(flat.equalize_mapping()[7], 7);
+    }
+
+    #[test]
+    fn clipping_keeps_the_total() {
+        let mut histogram = Histogram::from_values((0..1000).map(|i| if i < 900 { 0 } else { 1 }));
+        histogram.clip(50);
+        assert_eq!(histogram.total(), 1000);
+        // 900 excess values spread as 3 per bin plus one more in the first 132
+        assert_eq!(histogram.bins[0], 54);
+        assert_eq!(histogram.bins[1], 54);
+        assert_eq!(histogram.bins[131], 4);
+        assert_eq!(histogram.bins[132], 3);
+    }
+
+    #[test]
+    fn one_unclipped_tile_equalizes_globally() {
+        let buffer = RgbBuffer::from_fn(16, 12, |x, y| {
+            let v = (40 + x * 3 + y * 5) as u8;
+            Rgb([v, v, v])
+        });
+        assert_eq!(
+            equalize_adaptive(&buffer, GrayMethod::Bt601, 16, BINS as f32),
+            equalize(&buffer, GrayMethod::Bt601)
+        );
+    }
+}
@@ -1,10 +1,26 @@
-use std::{ffi::OsStr, path::PathBuf};
+use crate::error::{Error, Result};
+use crate::progress;
+use image::ImageDecoder;
+use std::borrow::Cow;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
 
 pub const COLOR_BLACK: image::Rgb<u8> = image::Rgb([0, 0, 0]);
 pub const COLOR_WHITE: image::Rgb<u8> = image::Rgb([255, 255, 255]);
 pub const COLOR_RED: image::Rgb<u8> = image::Rgb([230, 50, 50]);
 pub const COLOR_GREEN: image::Rgb<u8> = image::Rgb([34, 139, 34]);
 
+/// parses `RRGGBB` or `#RRGGBB` hex color
+pub fn parse_hex_color(text: &str) -> Option<image::Rgb<u8>> {
+    let hex = text.strip_prefix('#').unwrap_or(text);
+    if hex.len() != 6 || !hex.is_ascii() {
+        return None;
+    }
+    let channel = |idx: usize| u8::from_str_radix(&hex[idx..idx + 2], 16).ok();
+    Some(image::Rgb([channel(0)?, channel(2)?, channel(4)?]))
+}
+
 #[derive(Copy, Clone)]
 pub struct RgbF {
     pub r: f32,
@@ -25,118 +41,1229 @@ impl RgbF {
         }
     }
 
-    pub fn into_u8(&self) -> image::Rgb<u8> {
+    pub fn into_u8(self) -> image::Rgb<u8> {
         image::Rgb([
             (self.r * 255.0) as u8,
             (self.g * 255.0) as u8,
             (self.b * 255.0) as u8,
         ])
     }
+
+    /// rounds to the nearest byte, unlike the truncating `into_u8`
+    pub fn round_into_u8(self) -> image::Rgb<u8> {
+        let channel = |v: f32| (v * 255.0).round().clamp(0.0, 255.0) as u8;
+        image::Rgb([channel(self.r), channel(self.g), channel(self.b)])
+    }
+
+    pub fn from_u16(color: image::Rgb<u16>) -> RgbF {
+        RgbF {
+            r: color[0] as f32 / 65535.0,
+            g: color[1] as f32 / 65535.0,
+            b: color[2] as f32 / 65535.0,
+        }
+    }
+
+    pub fn into_linear(self) -> RgbF {
+        RgbF::new(
+            srgb_decode(self.r),
+            srgb_decode(self.g),
+            srgb_decode(self.b),
+        )
+    }
+
+    pub fn into_srgb(self) -> RgbF {
+        RgbF::new(
+            srgb_encode(self.r),
+            srgb_encode(self.g),
+            srgb_encode(self.b),
+        )
+    }
+}
+
+/// 16 bit linear light, enough precision to round trip every sRGB byte
+pub type LinearBuffer = image::ImageBuffer<image::Rgb<u16>, Vec<u16>>;
+
+/// subpixel types the averaging code runs on, `u8` for sRGB bytes and
+/// `u16` for linear light
+pub trait Light: image::Primitive + Into<u64> {
+    /// this channel type's value of an sRGB byte
+    fn from_srgb(value: u8) -> Self;
+
+    /// channel units per byte unit, exactly 1 for `u8`
+    fn byte_scale() -> f32 {
+        Self::DEFAULT_MAX_VALUE.into() as f32 / 255.0
+    }
+
+    /// the channel in 0..255 units, exact for `u8`
+    fn into_byte_units(self) -> f32 {
+        self.into() as f32 / Self::byte_scale()
+    }
+
+    /// nearest channel value of a value in 0..255 units
+    fn from_byte_units(value: f64) -> Self {
+        let max = Self::DEFAULT_MAX_VALUE.into() as f64;
+        let value = (value * Self::byte_scale() as f64).round().clamp(0.0, max);
+        Self::from(value).expect("clamped to the channel range")
+    }
+}
+
+impl Light for u8 {
+    fn from_srgb(value: u8) -> u8 {
+        value
+    }
+}
+
+impl Light for u16 {
+    fn from_srgb(value: u8) -> u16 {
+        (srgb_decode(value as f32 / 255.0) * 65535.0).round() as u16
+    }
+}
+
+pub fn buffer_into_linear(buffer: &image::ImageBuffer<image::Rgb<u8>, Vec<u8>>) -> LinearBuffer {
+    let lut: Vec<u16> = (0..=255u8).map(u16::from_srgb).collect();
+    LinearBuffer::from_fn(buffer.width(), buffer.height(), |x, y| {
+        image::Rgb(buffer.get_pixel(x, y).0.map(|v| lut[v as usize]))
+    })
+}
+
+pub fn buffer_from_linear(buffer: &LinearBuffer) -> image::ImageBuffer<image::Rgb<u8>, Vec<u8>> {
+    image::ImageBuffer::from_fn(buffer.width(), buffer.height(), |x, y| {
+        RgbF::from_u16(*buffer.get_pixel(x, y))
+            .into_srgb()
+            .round_into_u8()
+    })
+}
+
+/// CIE L*a*b* with D65 white point
+#[derive(Copy, Clone, Debug)]
+pub struct Lab {
+    pub l: f32,
+    pub a: f32,
+    pub b: f32,
+}
+
+// conversion constants taken from: https://en.wikipedia.org/wiki/CIELAB_color_space
+const D65: [f32; 3] = [0.95047, 1.0, 1.08883];
+
+impl Lab {
+    pub fn from_rgb(color: RgbF) -> Lab {
+        let c = color.into_linear();
+        let xyz = [
+            (0.4124 * c.r + 0.3576 * c.g + 0.1805 * c.b) / D65[0],
+            (0.2126 * c.r + 0.7152 * c.g + 0.0722 * c.b) / D65[1],
+            (0.0193 * c.r + 0.1192 * c.g + 0.9505 * c.b) / D65[2],
+        ];
+        let f = |t: f32| {
+            if t > 216.0 / 24389.0 {
+                t.cbrt()
+            } else {
+                t * 24389.0 / 27.0 / 116.0 + 16.0 / 116.0
+            }
+        };
+        let (fx, fy, fz) = (f(xyz[0]), f(xyz[1]), f(xyz[2]));
+        Lab {
+            l: 116.0 * fy - 16.0,
+            a: 500.0 * (fx - fy),
+            b: 200.0 * (fy - fz),
+        }
+    }
+
+    pub fn into_rgb(self) -> RgbF {
+        let fy = (self.l + 16.0) / 116.0;
+        let fx = fy + self.a / 500.0;
+        let fz = fy - self.b / 200.0;
+        let f_inv = |t: f32| {
+            if t > 6.0 / 29.0 {
+                t.powi(3)
+            } else {
+                (t - 16.0 / 116.0) * 116.0 * 27.0 / 24389.0
+            }
+        };
+        let (x, y, z) = (f_inv(fx) * D65[0], f_inv(fy) * D65[1], f_inv(fz) * D65[2]);
+        let linear = RgbF::new(
+            (3.2406 * x - 1.5372 * y - 0.4986 * z).clamp(0.0, 1.0),
+            (-0.9689 * x + 1.8758 * y + 0.0415 * z).clamp(0.0, 1.0),
+            (0.0557 * x - 0.2040 * y + 1.0570 * z).clamp(0.0, 1.0),
+        );
+        RgbF::new(
+            srgb_encode(linear.r),
+            srgb_encode(linear.g),
+            srgb_encode(linear.b),
+        )
+    }
+
+    pub fn distance_squared(&self, other: &Lab) -> f32 {
+        (self.l - other.l).powi(2) + (self.a - other.a).powi(2) + (self.b - other.b).powi(2)
+    }
+
+    // formula taken from: Sharma, Wu, Dalal "The CIEDE2000 Color-Difference Formula"
+    pub fn ciede2000(&self, other: &Lab) -> f32 {
+        let (l1, a1, b1) = (self.l as f64, self.a as f64, self.b as f64);
+        let (l2, a2, b2) = (other.l as f64, other.a as f64, other.b as f64);
+
+        let c_mean = ((a1.hypot(b1)) + (a2.hypot(b2))) / 2.0;
+        let g = 0.5 * (1.0 - (c_mean.powi(7) / (c_mean.powi(7) + 25f64.powi(7))).sqrt());
+        let (a1p, a2p) = (a1 * (1.0 + g), a2 * (1.0 + g));
+        let (c1p, c2p) = (a1p.hypot(b1), a2p.hypot(b2));
+        let hue = |b: f64, a: f64| {
+            if a == 0.0 && b == 0.0 {
+                0.0
+            } else {
+                b.atan2(a).to_degrees().rem_euclid(360.0)
+            }
+        };
+        let (h1p, h2p) = (hue(b1, a1p), hue(b2, a2p));
+
+        let dl = l2 - l1;
+        let dc = c2p - c1p;
+        let dh = if c1p * c2p == 0.0 {
+            0.0
+        } else if (h2p - h1p).abs() <= 180.0 {
+            h2p - h1p
+        } else if h2p - h1p > 180.0 {
+            h2p - h1p - 360.0
+        } else {
+            h2p - h1p + 360.0
+        };
+        let dh_big = 2.0 * (c1p * c2p).sqrt() * (dh / 2.0).to_radians().sin();
+
+        let l_mean = (l1 + l2) / 2.0;
+        let cp_mean = (c1p + c2p) / 2.0;
+        let hp_mean = if c1p * c2p == 0.0 {
+            h1p + h2p
+        } else if (h1p - h2p).abs() <= 180.0 {
+            (h1p + h2p) / 2.0
+        } else if h1p + h2p < 360.0 {
+            (h1p + h2p + 360.0) / 2.0
+        } else {
+            (h1p + h2p - 360.0) / 2.0
+        };
+        let t = 1.0 - 0.17 * (hp_mean - 30.0).to_radians().cos()
+            + 0.24 * (2.0 * hp_mean).to_radians().cos()
+            + 0.32 * (3.0 * hp_mean + 6.0).to_radians().cos()
+            - 0.20 * (4.0 * hp_mean - 63.0).to_radians().cos();
+        let d_theta = 30.0 * (-((hp_mean - 275.0) / 25.0).powi(2)).exp();
+        let r_c = 2.0 * (cp_mean.powi(7) / (cp_mean.powi(7) + 25f64.powi(7))).sqrt();
+        let s_l = 1.0 + 0.015 * (l_mean - 50.0).powi(2) / (20.0 + (l_mean - 50.0).powi(2)).sqrt();
+        let s_c = 1.0 + 0.045 * cp_mean;
+        let s_h = 1.0 + 0.015 * cp_mean * t;
+        let r_t = -(2.0 * d_theta).to_radians().sin() * r_c;
+
+        let (tl, tc, th) = (dl / s_l, dc / s_c, dh_big / s_h);
+        (tl * tl + tc * tc + th * th + r_t * tc * th).sqrt() as f32
+    }
+}
+
+// transfer functions taken from: https://en.wikipedia.org/wiki/SRGB
+pub fn srgb_decode(value: f32) -> f32 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// linear light of an sRGB byte in 0..1, table backed for per tap use
+pub fn srgb_decode_u8(value: u8) -> f32 {
+    static TABLE: OnceLock<[f32; 256]> = OnceLock::new();
+    TABLE.get_or_init(|| std::array::from_fn(|v| srgb_decode(v as f32 / 255.0)))[value as usize]
+}
+
+pub fn srgb_encode(value: f32) -> f32 {
+    if value <= 0.0031308 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+#[derive(Copy, Clone, Default)]
+pub enum GrayMethod {
+    /// luma with BT.601 weights on gamma-encoded values
+    #[default]
+    Bt601,
+    /// luma with BT.709 weights on gamma-encoded values
+    Bt709,
+    /// luma with BT.2100 weights on gamma-encoded values
+    Bt2100,
+    /// mean of the three channels
+    Average,
+    /// HSL lightness, mean of the max and min channel, also named desaturate
+    Lightness,
+    /// HSV value, max channel
+    Value,
+    /// red channel alone
+    Red,
+    /// green channel alone
+    Green,
+    /// blue channel alone
+    Blue,
+    /// relative luminance computed in linear light and re-encoded to sRGB
+    Linear,
+}
+
+impl GrayMethod {
+    pub const NAMES: &'static str =
+        "bt601, bt709, bt2100, average, lightness, desaturate, value, red, green, blue, linear";
+
+    pub fn from_name(name: &str) -> Option<GrayMethod> {
+        let method = match name {
+            "bt601" => GrayMethod::Bt601,
+            "bt709" => GrayMethod::Bt709,
+            "bt2100" => GrayMethod::Bt2100,
+            "average" => GrayMethod::Average,
+            "lightness" | "desaturate" => GrayMethod::Lightness,
+            "value" => GrayMethod::Value,
+            "red" => GrayMethod::Red,
+            "green" => GrayMethod::Green,
+            "blue" => GrayMethod::Blue,
+            "linear" => GrayMethod::Linear,
+            _ => return None,
+        };
+        Some(method)
+    }
+
+    /// gray intensity of a color in 0.0..=1.0 range
+    // weights taken from: https://en.wikipedia.org/wiki/Grayscale
+    // and https://en.wikipedia.org/wiki/Rec._2100
+    pub fn intensity(self, color: RgbF) -> f32 {
+        match self {
+            GrayMethod::Bt601 => color.r * 0.299 + color.g * 0.587 + color.b * 0.114,
+            GrayMethod::Bt709 => color.r * 0.2126 + color.g * 0.7152 + color.b * 0.0722,
+            GrayMethod::Bt2100 => color.r * 0.2627 + color.g * 0.6780 + color.b * 0.0593,
+            GrayMethod::Average => (color.r + color.g + color.b) / 3.0,
+            GrayMethod::Lightness => {
+                let max = color.r.max(color.g).max(color.b);
+                let min = color.r.min(color.g).min(color.b);
+                (max + min) / 2.0
+            }
+            GrayMethod::Value => color.r.max(color.g).max(color.b),
+            GrayMethod::Red => color.r,
+            GrayMethod::Green => color.g,
+            GrayMethod::Blue => color.b,
+            GrayMethod::Linear => {
+                let linear = color.into_linear();
+                let luminance = linear.r * 0.2126 + linear.g * 0.7152 + linear.b * 0.0722;
+                srgb_encode(luminance)
+            }
+        }
+        .clamp(0.0, 1.0)
+    }
+
+    pub fn gray_u8(self, color: image::Rgb<u8>) -> u8 {
+        (self.intensity(RgbF::from_u8(color)) * 255.0).round() as u8
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Rect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Rect {
+    pub fn new(x: u32, y: u32, width: u32, height: u32) -> Rect {
+        Rect {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    pub fn area(&self) -> u64 {
+        self.width as u64 * self.height as u64
+    }
+
+    pub fn center(&self) -> (f32, f32) {
+        (
+            self.x as f32 + self.width as f32 / 2.0,
+            self.y as f32 + self.height as f32 / 2.0,
+        )
+    }
+
+    pub fn intersection(&self, other: &Rect) -> Option<Rect> {
+        // in u64 so edges of boxes reaching past u32::MAX do not overflow
+        let x0 = self.x.max(other.x) as u64;
+        let y0 = self.y.max(other.y) as u64;
+        let x1 = (self.x as u64 + self.width as u64).min(other.x as u64 + other.width as u64);
+        let y1 = (self.y as u64 + self.height as u64).min(other.y as u64 + other.height as u64);
+        if x0 < x1 && y0 < y1 {
+            Some(Rect::new(
+                x0 as u32,
+                y0 as u32,
+                (x1 - x0) as u32,
+                (y1 - y0) as u32,
+            ))
+        } else {
+            None
+        }
+    }
+
+    /// intersection over union
+    pub fn iou(&self, other: &Rect) -> f32 {
+        let intersection = self.intersection(other).map_or(0, |rect| rect.area());
+        let union = self.area() + other.area() - intersection;
+        if union == 0 {
+            0.0
+        } else {
+            intersection as f32 / union as f32
+        }
+    }
 }
 
 pub struct ImageInfo {
     image_dir: PathBuf,
     name: String,
+    relative: PathBuf,
+    save: SaveOptions,
 }
 
 impl ImageInfo {
-    pub fn save_path_concat(&self, postfix: &str, format: image::ImageFormat) -> PathBuf {
-        self.image_dir.join(format!(
-            "{}_{}.{}",
-            self.name,
-            postfix,
-            format.extensions_str()[0]
-        ))
+    pub fn name(&self) -> &str {
+        &self.name
     }
-}
 
-pub fn open_and_setup_output(target: &PathBuf) -> Vec<(image::DynamicImage, ImageInfo)> {
-    if !target.exists() {
-        eprintln!("path was not found `{}`", target.to_string_lossy());
-        return Vec::new();
+    /// source file relative to the closest common directory of the inputs
+    pub fn relative_path(&self) -> &Path {
+        &self.relative
     }
 
-    let mut image_paths = Vec::new();
-    if target.is_file() {
-        if ext_is_supported(target.extension()) {
-            image_paths.push(target.clone());
+    /// result path with the extension of the configured output format
+    pub fn save_path_concat(&self, postfix: &str) -> SavePath {
+        SavePath {
+            path: self.image_dir.join(format!(
+                "{}_{}.{}",
+                self.name,
+                postfix,
+                self.save.format.extension()
+            )),
+            options: self.save,
         }
     }
-    if target.is_dir() {
-        let read_dir = std::fs::read_dir(target).expect("read dir");
-        for entry in read_dir.flatten() {
-            let path = entry.path();
-            if ext_is_supported(path.extension()) {
-                image_paths.push(path);
+}
+
+/// result file and how it is encoded
+pub struct SavePath {
+    path: PathBuf,
+    options: SaveOptions,
+}
+
+/// an image file found under the targets, decoded only when opened
+pub struct ImageInput {
+    path: PathBuf,
+    info: ImageInfo,
+    exif_orientation: bool,
+}
+
+impl ImageInput {
+    /// true when the policy keeps existing files and every result named by
+    /// `postfixes` is already saved, an empty list is never complete
+    fn has_results(&self, postfixes: &[String]) -> bool {
+        self.info.save.existing == ExistingFiles::Skip
+            && !postfixes.is_empty()
+            && postfixes
+                .iter()
+                .all(|postfix| self.info.save_path_concat(postfix).path.exists())
+    }
+
+    /// creates the result folder and decodes the image
+    pub fn open(self) -> Result<(image::DynamicImage, ImageInfo)> {
+        std::fs::create_dir_all(&self.info.image_dir)
+            .map_err(|err| Error::io(&self.info.image_dir, err))?;
+        Ok((image_open(&self.path, self.exif_orientation)?, self.info))
+    }
+}
+
+/// every image under `targets`, the result folders mirror the inputs
+/// relative to their closest common directory
+///
+/// directories are listed and their files sniffed only when the iterator
+/// reaches them, decoding is deferred until a worker calls `ImageInput::open`
+pub fn open_and_setup_output<'a>(targets: &[PathBuf], batch: &'a Batch) -> Inputs<'a> {
+    let mut pending = Vec::new();
+    for target in targets {
+        let Ok(absolute) = target.canonicalize() else {
+            progress::eprintln(format!("path was not found `{}`", target.to_string_lossy()));
+            continue;
+        };
+        pending.push(if absolute.is_dir() {
+            Pending {
+                given: target.clone(),
+                base: absolute,
+                relative: PathBuf::new(),
+                is_dir: true,
             }
-        }
+        } else {
+            Pending {
+                given: target.parent().unwrap_or(Path::new("")).to_path_buf(),
+                base: absolute.parent().expect("file has a parent").to_path_buf(),
+                relative: PathBuf::from(target.file_name().expect("file name")),
+                is_dir: false,
+            }
+        });
     }
+    let root = pending
+        .iter()
+        .map(|target| target.base.clone())
+        .reduce(|common, base| common_ancestor(&common, &base))
+        .unwrap_or_default();
+    pending.reverse();
+    Inputs {
+        batch,
+        root,
+        pending,
+        ready: std::collections::VecDeque::new(),
+        seen: std::collections::HashSet::new(),
+        visited: std::collections::HashSet::new(),
+        taken: std::collections::HashSet::new(),
+    }
+}
 
-    let mut image_inputs = Vec::new();
+/// a directory still to list or a file given directly, `given` and `base`
+/// are its target directory as given and canonicalized, `relative` is below
+/// both
+struct Pending {
+    given: PathBuf,
+    base: PathBuf,
+    relative: PathBuf,
+    is_dir: bool,
+}
+
+/// lazy walk over the targets, one directory listing is held at a time,
+/// files given directly bypass the include and exclude globs
+pub struct Inputs<'a> {
+    batch: &'a Batch,
+    /// closest common directory of the targets
+    root: PathBuf,
+    /// stack of what is left to walk, the next entry last
+    pending: Vec<Pending>,
+    /// images of the last listed directory
+    ready: std::collections::VecDeque<ImageInput>,
+    /// canonical files already yielded
+    seen: std::collections::HashSet<PathBuf>,
+    /// canonical directories already listed, so symlinks that point back up
+    /// the tree do not loop
+    visited: std::collections::HashSet<PathBuf>,
+    /// result folders given out, relative to the output directory
+    taken: std::collections::HashSet<PathBuf>,
+}
 
-    let results_path = PathBuf::from("image_process_results");
-    if !results_path.exists() {
-        std::fs::create_dir(&results_path).expect("dir created");
+impl Iterator for Inputs<'_> {
+    type Item = ImageInput;
+
+    fn next(&mut self) -> Option<ImageInput> {
+        loop {
+            if let Some(input) = self.ready.pop_front() {
+                return Some(input);
+            }
+            let pending = self.pending.pop()?;
+            let files = if pending.is_dir {
+                self.list_dir(&pending)
+            } else {
+                vec![pending.relative.clone()]
+            };
+            self.add_images(&pending, files);
+            progress::discovered(self.ready.len());
+        }
     }
+}
 
-    for path in image_paths {
-        let name = path
-            .file_stem()
-            .expect("image filename")
-            .to_str()
-            .expect("utf8 filename");
-        let image_dir = results_path.join(name);
-        if !image_dir.exists() {
-            std::fs::create_dir(&image_dir).expect("dir created");
+impl Inputs<'_> {
+    /// files of the pending directory filtered by the input globs, its
+    /// subdirectories are queued ahead of the rest when recursive
+    fn list_dir(&mut self, dir: &Pending) -> Vec<PathBuf> {
+        let path = dir.given.join(&dir.relative);
+        if let Ok(canonical) = path.canonicalize() {
+            if !self.visited.insert(canonical) {
+                return Vec::new();
+            }
+        }
+        let options = &self.batch.input;
+        let matches = |patterns: &[glob::Pattern], path: &Path| {
+            let match_options = glob::MatchOptions {
+                case_sensitive: false,
+                ..Default::default()
+            };
+            patterns
+                .iter()
+                .any(|p| p.matches_path_with(path, match_options))
+        };
+        let mut entries: Vec<PathBuf> = match std::fs::read_dir(&path) {
+            Ok(read_dir) => read_dir.flatten().map(|entry| entry.path()).collect(),
+            Err(err) => {
+                progress::eprintln(format!(
+                    "directory was not read `{}`, {}",
+                    path.to_string_lossy(),
+                    err
+                ));
+                return Vec::new();
+            }
+        };
+        entries.sort();
+        let mut files = Vec::new();
+        let mut subdirs = Vec::new();
+        for path in entries {
+            let entry_relative = dir.relative.join(path.file_name().expect("entry name"));
+            if matches(&options.exclude, &entry_relative) {
+                continue;
+            }
+            if path.is_dir() {
+                if options.recursive {
+                    subdirs.push(Pending {
+                        given: dir.given.clone(),
+                        base: dir.base.clone(),
+                        relative: entry_relative,
+                        is_dir: true,
+                    });
+                }
+            } else if options.include.is_empty() || matches(&options.include, &entry_relative) {
+                files.push(entry_relative);
+            }
         }
-        image_inputs.push((
-            image_open(&path),
-            ImageInfo {
-                image_dir,
-                name: name.to_string(),
-            },
-        ));
+        self.pending.extend(subdirs.into_iter().rev());
+        files
     }
 
-    image_inputs
+    /// queues the readable `files` of one directory with their result folder
+    ///
+    /// the folder is the file stem, files sharing a stem or whose stem names a
+    /// folder already given out get their extension appended and a counter
+    /// when that name is taken too, so results never mix
+    fn add_images(&mut self, dir: &Pending, files: Vec<PathBuf>) {
+        let mut images = Vec::new();
+        for relative in files {
+            if !self.seen.insert(dir.base.join(&relative)) {
+                continue;
+            }
+            let path = dir.given.join(&relative);
+            match detect_format(&path) {
+                Ok(_) => images.push((
+                    path,
+                    dir.base
+                        .strip_prefix(&self.root)
+                        .expect("under root")
+                        .join(&relative),
+                )),
+                Err(reason) => {
+                    progress::println(format!("skipped: `{}`, {}", path.to_string_lossy(), reason))
+                }
+            }
+        }
+
+        let mut stem_counts = std::collections::HashMap::new();
+        for (_, relative) in &images {
+            *stem_counts
+                .entry(relative.file_stem().map(|s| s.to_os_string()))
+                .or_insert(0) += 1;
+        }
+        // unique stems claim their plain folder first
+        let plain: Vec<bool> = images
+            .iter()
+            .map(|(_, relative)| {
+                stem_counts[&relative.file_stem().map(|s| s.to_os_string())] == 1
+                    && self.taken.insert(relative.with_extension(""))
+            })
+            .collect();
+        for ((path, relative), plain) in images.into_iter().zip(plain) {
+            let folder = if plain {
+                relative.with_extension("")
+            } else {
+                let stem = relative.file_stem().expect("file stem").to_string_lossy();
+                let base = match relative.extension() {
+                    Some(ext) => format!("{}_{}", stem, ext.to_string_lossy()),
+                    None => stem.to_string(),
+                };
+                let mut folder = relative.with_file_name(&base);
+                let mut counter = 2;
+                while !self.taken.insert(folder.clone()) {
+                    folder = relative.with_file_name(format!("{}_{}", base, counter));
+                    counter += 1;
+                }
+                folder
+            };
+            self.ready.push_back(ImageInput {
+                info: ImageInfo {
+                    image_dir: self.batch.output.dir.join(folder),
+                    name: path
+                        .file_stem()
+                        .expect("image filename")
+                        .to_string_lossy()
+                        .to_string(),
+                    relative,
+                    save: self.batch.output.save,
+                },
+                path,
+                exif_orientation: self.batch.exif_orientation,
+            });
+        }
+    }
 }
 
-fn ext_is_supported(ext: Option<&OsStr>) -> bool {
-    let ext = ext.unwrap_or_default().to_str().expect("utf8");
-    matches!(ext, "png" | "jpg" | "jpeg")
+/// runs `process` on every image under `targets` across the worker pool,
+/// each worker holds one decoded image at a time, progress is shown while
+/// running and the time spent on each file is summarized at the end,
+/// a failing image is reported and the rest of the batch still runs
+///
+/// `results` are the postfixes of the files `process` saves, when the policy
+/// keeps existing files and all of them exist the image is not decoded, an
+/// empty list always processes
+pub fn for_each_image<F>(
+    batch: &Batch,
+    targets: &[PathBuf],
+    results: &[String],
+    process: F,
+) -> Result<()>
+where
+    F: Fn(image::DynamicImage, ImageInfo) -> Result<()> + Sync,
+{
+    use rayon::iter::{ParallelBridge, ParallelIterator};
+
+    let started = Instant::now();
+    progress::start();
+    // errors on the input itself already name it
+    let describe = |path: &Path, err: &Error| match err.path() {
+        Some(err_path) if err_path == path => err.to_string(),
+        _ => format!("`{}` {}", path.to_string_lossy(), err),
+    };
+    // decode and process times, none when the results were kept
+    type Outcome = Result<Option<(Duration, Duration)>>;
+    let mut outcomes: Vec<(PathBuf, Outcome)> = open_and_setup_output(targets, batch)
+        .par_bridge()
+        .map(|input| {
+            let path = input.path.clone();
+            if input.has_results(results) {
+                progress::println(format!("kept existing: `{}`", path.to_string_lossy()));
+                progress::advance();
+                return (path, Ok(None));
+            }
+            let decode_started = Instant::now();
+            let outcome = input.open().and_then(|(image, info)| {
+                let decoded = decode_started.elapsed();
+                let process_started = Instant::now();
+                process(image, info)?;
+                Ok(Some((decoded, process_started.elapsed())))
+            });
+            if let Err(err) = &outcome {
+                progress::eprintln(format!("failed: {}", describe(&path, err)));
+            }
+            progress::advance();
+            (path, outcome)
+        })
+        .collect();
+    progress::finish();
+    // workers finish in any order
+    outcomes.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let total = outcomes.len();
+    let mut timings = Vec::new();
+    let mut failures = Vec::new();
+    for (path, outcome) in outcomes {
+        match outcome {
+            Ok(Some((decoded, processed))) => {
+                timings.push((path.to_string_lossy().to_string(), decoded, processed))
+            }
+            Ok(None) => {}
+            Err(err) => failures.push(describe(&path, &err)),
+        }
+    }
+    print_timings(&timings, started.elapsed());
+    if failures.is_empty() {
+        return Ok(());
+    }
+    eprintln!("failures:");
+    for failure in &failures {
+        eprintln!("  {}", failure);
+    }
+    Err(Error::Batch {
+        failed: failures.len(),
+        total,
+    })
 }
 
-fn image_open(path: &PathBuf) -> image::DynamicImage {
-    let image = image::open(path).expect("image open");
+fn print_timings(timings: &[(String, Duration, Duration)], wall: Duration) {
+    if timings.is_empty() {
+        return;
+    }
+    let busy: Duration = timings.iter().map(|(_, d, p)| *d + *p).sum();
     println!(
-        "{}",
-        format!(
-            "opened: `{}`, color: `{:?}`, size `{}x{}`",
-            path.to_string_lossy(),
-            image.color(),
-            image.width(),
-            image.height()
-        )
+        "timing: {} images in {:.2} s, {:.2} s of work across {} workers",
+        timings.len(),
+        wall.as_secs_f64(),
+        busy.as_secs_f64(),
+        rayon::current_num_threads().min(timings.len())
     );
-    image
+    for (label, decoded, processed) in timings {
+        println!(
+            "  `{}` decode {} ms, process {} ms",
+            label,
+            decoded.as_millis(),
+            processed.as_millis()
+        );
+    }
+}
+
+/// which files directory targets contribute
+#[derive(Default)]
+pub struct InputOptions {
+    /// descend into subdirectories
+    pub recursive: bool,
+    /// when not empty files must match one, paths are relative to the target
+    pub include: Vec<glob::Pattern>,
+    /// matching files are skipped and matching directories are not entered
+    pub exclude: Vec<glob::Pattern>,
+}
+
+fn common_ancestor(a: &Path, b: &Path) -> PathBuf {
+    a.components()
+        .zip(b.components())
+        .take_while(|(x, y)| x == y)
+        .map(|(x, _)| x)
+        .collect()
+}
+
+/// format of the file at `path` from its leading bytes, the extension in
+/// any letter case decides for formats without a signature
+fn detect_format(path: &Path) -> std::result::Result<image::ImageFormat, String> {
+    let reader = image::ImageReader::open(path)
+        .and_then(|reader| reader.with_guessed_format())
+        .map_err(|err| format!("unreadable: {}", err))?;
+    match reader.format() {
+        // the default avif feature only encodes, decoding needs the native one
+        Some(image::ImageFormat::Avif) => Err("no avif decoder in this build".to_string()),
+        Some(format) if format.reading_enabled() => Ok(format),
+        Some(format) => Err(format!("no {:?} decoder in this build", format)),
+        None => Err("not a recognized image format".to_string()),
+    }
+}
+
+/// encoding of saved results
+#[derive(Copy, Clone, Default, PartialEq)]
+pub enum OutputFormat {
+    #[default]
+    Png,
+    /// lossy, alpha is dropped
+    Jpeg,
+    /// lossless
+    WebP,
+    Tiff,
+    Bmp,
+    /// binary ppm, pgm or pam for images with alpha
+    Pnm,
+    Qoi,
+}
+
+impl OutputFormat {
+    pub const NAMES: &'static str = "png, jpeg, webp, tiff, bmp, pnm, qoi";
+
+    pub fn from_name(name: &str) -> Option<OutputFormat> {
+        Some(match name {
+            "png" => OutputFormat::Png,
+            "jpeg" => OutputFormat::Jpeg,
+            "webp" => OutputFormat::WebP,
+            "tiff" => OutputFormat::Tiff,
+            "bmp" => OutputFormat::Bmp,
+            "pnm" => OutputFormat::Pnm,
+            "qoi" => OutputFormat::Qoi,
+            _ => return None,
+        })
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            OutputFormat::Png => "png",
+            OutputFormat::Jpeg => "jpg",
+            OutputFormat::WebP => "webp",
+            OutputFormat::Tiff => "tiff",
+            OutputFormat::Bmp => "bmp",
+            OutputFormat::Pnm => "pnm",
+            OutputFormat::Qoi => "qoi",
+        }
+    }
+}
+
+/// zlib effort of png results
+#[derive(Copy, Clone, Default)]
+pub enum PngCompression {
+    #[default]
+    Fast,
+    Default,
+    Best,
+}
+
+impl PngCompression {
+    pub const NAMES: &'static str = "fast, default, best";
+
+    pub fn from_name(name: &str) -> Option<PngCompression> {
+        Some(match name {
+            "fast" => PngCompression::Fast,
+            "default" => PngCompression::Default,
+            "best" => PngCompression::Best,
+            _ => return None,
+        })
+    }
+}
+
+/// png row filter, adaptive picks the best one per row
+#[derive(Copy, Clone, Default)]
+pub enum PngFilter {
+    None,
+    Sub,
+    Up,
+    Avg,
+    Paeth,
+    #[default]
+    Adaptive,
+}
+
+impl PngFilter {
+    pub const NAMES: &'static str = "none, sub, up, avg, paeth, adaptive";
+
+    pub fn from_name(name: &str) -> Option<PngFilter> {
+        Some(match name {
+            "none" => PngFilter::None,
+            "sub" => PngFilter::Sub,
+            "up" => PngFilter::Up,
+            "avg" => PngFilter::Avg,
+            "paeth" => PngFilter::Paeth,
+            "adaptive" => PngFilter::Adaptive,
+            _ => return None,
+        })
+    }
 }
 
-pub fn image_buffer_save_png(
+/// what saving does when the result file already exists
+#[derive(Copy, Clone, Default, PartialEq)]
+pub enum ExistingFiles {
+    #[default]
+    Overwrite,
+    Skip,
+}
+
+impl ExistingFiles {
+    pub const NAMES: &'static str = "overwrite, skip";
+
+    pub fn from_name(name: &str) -> Option<ExistingFiles> {
+        Some(match name {
+            "overwrite" => ExistingFiles::Overwrite,
+            "skip" => ExistingFiles::Skip,
+            _ => return None,
+        })
+    }
+}
+
+/// how results are encoded and whether existing files are replaced
+#[derive(Copy, Clone)]
+pub struct SaveOptions {
+    pub format: OutputFormat,
+    /// 1..=100
+    pub jpeg_quality: u8,
+    pub png_compression: PngCompression,
+    pub png_filter: PngFilter,
+    pub existing: ExistingFiles,
+}
+
+impl Default for SaveOptions {
+    fn default() -> SaveOptions {
+        SaveOptions {
+            format: OutputFormat::Png,
+            jpeg_quality: 90,
+            png_compression: PngCompression::Fast,
+            png_filter: PngFilter::Adaptive,
+            existing: ExistingFiles::Overwrite,
+        }
+    }
+}
+
+/// where and how results are saved
+pub struct OutputOptions {
+    /// results root, the per image folders are created in it
+    pub dir: PathBuf,
+    pub save: SaveOptions,
+}
+
+impl Default for OutputOptions {
+    fn default() -> OutputOptions {
+        OutputOptions {
+            dir: PathBuf::from("image_process_results"),
+            save: SaveOptions::default(),
+        }
+    }
+}
+
+/// settings shared by every image of a run, built once from the command
+/// line and handed to `for_each_image`
+pub struct Batch {
+    pub input: InputOptions,
+    pub output: OutputOptions,
+    /// rotate images upright from their EXIF orientation on load
+    pub exif_orientation: bool,
+}
+
+impl Default for Batch {
+    fn default() -> Batch {
+        Batch {
+            input: InputOptions::default(),
+            output: OutputOptions::default(),
+            exif_orientation: true,
+        }
+    }
+}
+
+/// decodes `path`, rotated upright from its EXIF orientation when
+/// `exif_orientation` is set
+pub fn image_open(path: &Path, exif_orientation: bool) -> Result<image::DynamicImage> {
+    let mut decoder = image::ImageReader::open(path)
+        .and_then(|reader| reader.with_guessed_format())
+        .map_err(|err| Error::io(path, err))?
+        .into_decoder()
+        .map_err(|err| Error::decode(path, err))?;
+    let orientation = decoder
+        .orientation()
+        .unwrap_or(image::metadata::Orientation::NoTransforms);
+    let mut image =
+        image::DynamicImage::from_decoder(decoder).map_err(|err| Error::decode(path, err))?;
+    let oriented = exif_orientation && orientation != image::metadata::Orientation::NoTransforms;
+    if oriented {
+        image.apply_orientation(orientation);
+    }
+    progress::println(format!(
+        "opened: `{}`, color: `{:?}`, size `{}x{}`{}",
+        path.to_string_lossy(),
+        image.color(),
+        image.width(),
+        image.height(),
+        if oriented {
+            format!(", exif orientation `{:?}` applied", orientation)
+        } else {
+            String::new()
+        }
+    ));
+    Ok(image)
+}
+
+pub fn image_buffer_save(
     buffer: image::ImageBuffer<image::Rgb<u8>, Vec<u8>>,
-    save_path: &PathBuf,
-) {
-    buffer
-        .save_with_format(save_path, image::ImageFormat::Png)
-        .expect("save with format");
-    println!("saved: `{}`", save_path.to_string_lossy());
+    save_path: &SavePath,
+) -> Result<()> {
+    save(image::DynamicImage::ImageRgb8(buffer), save_path)
+}
+
+/// keeps the color type and bit depth of `image` where the format allows
+pub fn image_save(image: &image::DynamicImage, save_path: &SavePath) -> Result<()> {
+    save(image.clone(), save_path)
+}
+
+pub fn image_buffer_rgba_save(
+    buffer: image::ImageBuffer<image::Rgba<u8>, Vec<u8>>,
+    save_path: &SavePath,
+) -> Result<()> {
+    save(image::DynamicImage::ImageRgba8(buffer), save_path)
+}
+
+/// saves palette indices as an indexed png with the palette embedded,
+/// other formats get the palette colors expanded
+pub fn image_indexed_save(
+    width: u32,
+    height: u32,
+    indices: &[u8],
+    palette: &[image::Rgb<u8>],
+    save_path: &SavePath,
+) -> Result<()> {
+    if save_path.options.format != OutputFormat::Png {
+        let buffer = image::ImageBuffer::from_fn(width, height, |x, y| {
+            palette[indices[(y * width + x) as usize] as usize]
+        });
+        return image_buffer_save(buffer, save_path);
+    }
+    let SavePath {
+        path: save_path,
+        options,
+    } = save_path;
+    if keep_existing(save_path, options) {
+        return Ok(());
+    }
+
+    let file = std::fs::File::create(save_path).map_err(|err| Error::io(save_path, err))?;
+    let mut writer = std::io::BufWriter::new(file);
+    let palette = palette.iter().flat_map(|c| c.0).collect::<Vec<u8>>();
+    write_png(
+        &mut writer,
+        options,
+        (width, height),
+        (png::ColorType::Indexed, png::BitDepth::Eight),
+        Some(palette),
+        indices,
+    )
+    .map_err(|err| Error::encode(save_path, err))?;
+    std::io::Write::flush(&mut writer).map_err(|err| Error::io(save_path, err))?;
+    progress::println(format!("saved: `{}`", save_path.to_string_lossy()));
+    Ok(())
 }
 
-pub fn image_buffer_luma16_save_png(
+pub fn image_buffer_luma16_save(
     buffer: image::ImageBuffer<image::Luma<u16>, Vec<u16>>,
-    save_path: &PathBuf,
-) {
-    buffer
-        .save_with_format(save_path, image::ImageFormat::Png)
-        .expect("save with format");
-    println!("saved: `{}`", save_path.to_string_lossy());
+    save_path: &SavePath,
+) -> Result<()> {
+    save(image::DynamicImage::ImageLuma16(buffer), save_path)
+}
+
+/// true when `save_path` exists and the policy keeps existing files
+fn keep_existing(save_path: &Path, options: &SaveOptions) -> bool {
+    let keep = options.existing == ExistingFiles::Skip && save_path.exists();
+    if keep {
+        progress::println(format!("kept existing: `{}`", save_path.to_string_lossy()));
+    }
+    keep
+}
+
+/// encodes with the configured format and encoder options, color types the
+/// format cannot store are converted to the closest one it can
+fn save(image: image::DynamicImage, save_path: &SavePath) -> Result<()> {
+    use image::codecs::{
+        bmp::BmpEncoder,
+        jpeg::JpegEncoder,
+        pnm::{PnmEncoder, PnmSubtype, SampleEncoding},
+        qoi::QoiEncoder,
+        tiff::TiffEncoder,
+        webp::WebPEncoder,
+    };
+    use image::{DynamicImage, GenericImageView};
+
+    let SavePath {
+        path: save_path,
+        options,
+    } = save_path;
+    if keep_existing(save_path, options) {
+        return Ok(());
+    }
+    let gray = !image.color().has_color();
+    let alpha = image.color().has_alpha();
+    let image = match (options.format, gray, alpha) {
+        (OutputFormat::Png | OutputFormat::Tiff, _, _) => image,
+        (OutputFormat::Jpeg, true, _) => DynamicImage::ImageLuma8(image.into_luma8()),
+        (OutputFormat::Jpeg, false, _) => DynamicImage::ImageRgb8(image.into_rgb8()),
+        (OutputFormat::Qoi, _, true) => DynamicImage::ImageRgba8(image.into_rgba8()),
+        (OutputFormat::Qoi, _, false) => DynamicImage::ImageRgb8(image.into_rgb8()),
+        (_, true, false) => DynamicImage::ImageLuma8(image.into_luma8()),
+        (_, true, true) => DynamicImage::ImageLumaA8(image.into_luma_alpha8()),
+        (_, false, false) => DynamicImage::ImageRgb8(image.into_rgb8()),
+        (_, false, true) => DynamicImage::ImageRgba8(image.into_rgba8()),
+    };
+
+    let file = std::fs::File::create(save_path).map_err(|err| Error::io(save_path, err))?;
+    let mut writer = std::io::BufWriter::new(file);
+    match options.format {
+        OutputFormat::Png => {
+            let (color, samples) = png_samples(&image);
+            write_png(
+                &mut writer,
+                options,
+                image.dimensions(),
+                color,
+                None,
+                &samples,
+            )
+            .map_err(|err| match err {
+                png::EncodingError::IoError(err) => image::ImageError::IoError(err),
+                err => image::ImageError::Encoding(image::error::EncodingError::new(
+                    image::ImageFormat::Png.into(),
+                    err,
+                )),
+            })
+        }
+        OutputFormat::Jpeg => image.write_with_encoder(JpegEncoder::new_with_quality(
+            &mut writer,
+            options.jpeg_quality,
+        )),
+        OutputFormat::WebP => image.write_with_encoder(WebPEncoder::new_lossless(&mut writer)),
+        OutputFormat::Tiff => image.write_with_encoder(TiffEncoder::new(&mut writer)),
+        OutputFormat::Bmp => image.write_with_encoder(BmpEncoder::new(&mut writer)),
+        OutputFormat::Pnm => {
+            let subtype = match (gray, alpha) {
+                (_, true) => PnmSubtype::ArbitraryMap,
+                (true, false) => PnmSubtype::Graymap(SampleEncoding::Binary),
+                (false, false) => PnmSubtype::Pixmap(SampleEncoding::Binary),
+            };
+            image.write_with_encoder(PnmEncoder::new(&mut writer).with_subtype(subtype))
+        }
+        OutputFormat::Qoi => image.write_with_encoder(QoiEncoder::new(&mut writer)),
+    }
+    .map_err(|err| match err {
+        image::ImageError::IoError(err) => Error::io(save_path, err),
+        err => Error::encode(save_path, err),
+    })?;
+    std::io::Write::flush(&mut writer).map_err(|err| Error::io(save_path, err))?;
+    progress::println(format!("saved: `{}`", save_path.to_string_lossy()));
+    Ok(())
+}
+
+/// png color layout and big endian samples of an image, float images are
+/// stored as 16 bit rgba
+fn png_samples(image: &image::DynamicImage) -> ((png::ColorType, png::BitDepth), Cow<'_, [u8]>) {
+    use image::ColorType as Color;
+    use png::{BitDepth, ColorType};
+
+    let big_endian = |native: &[u8]| {
+        native
+            .chunks_exact(2)
+            .flat_map(|pair| u16::from_ne_bytes([pair[0], pair[1]]).to_be_bytes())
+            .collect()
+    };
+    let layout = match image.color() {
+        Color::L8 => (ColorType::Grayscale, BitDepth::Eight),
+        Color::La8 => (ColorType::GrayscaleAlpha, BitDepth::Eight),
+        Color::Rgb8 => (ColorType::Rgb, BitDepth::Eight),
+        Color::Rgba8 => (ColorType::Rgba, BitDepth::Eight),
+        Color::L16 => (ColorType::Grayscale, BitDepth::Sixteen),
+        Color::La16 => (ColorType::GrayscaleAlpha, BitDepth::Sixteen),
+        Color::Rgb16 => (ColorType::Rgb, BitDepth::Sixteen),
+        Color::Rgba16 => (ColorType::Rgba, BitDepth::Sixteen),
+        _ => {
+            let rgba = image::DynamicImage::ImageRgba16(image.to_rgba16());
+            return (
+                (ColorType::Rgba, BitDepth::Sixteen),
+                Cow::Owned(big_endian(rgba.as_bytes())),
+            );
+        }
+    };
+    let samples = match layout.1 {
+        BitDepth::Sixteen => Cow::Owned(big_endian(image.as_bytes())),
+        _ => Cow::Borrowed(image.as_bytes()),
+    };
+    (layout, samples)
+}
+
+/// encodes png data with the configured compression and row filter, every
+/// png result is written through here
+fn write_png(
+    writer: impl std::io::Write,
+    options: &SaveOptions,
+    (width, height): (u32, u32),
+    (color, depth): (png::ColorType, png::BitDepth),
+    palette: Option<Vec<u8>>,
+    data: &[u8],
+) -> std::result::Result<(), png::EncodingError> {
+    let mut encoder = png::Encoder::new(writer, width, height);
+    encoder.set_color(color);
+    encoder.set_depth(depth);
+    if let Some(palette) = palette {
+        encoder.set_palette(palette);
+    }
+    encoder.set_compression(match options.png_compression {
+        PngCompression::Fast => png::Compression::Fast,
+        PngCompression::Default => png::Compression::Balanced,
+        PngCompression::Best => png::Compression::High,
+    });
+    encoder.set_filter(match options.png_filter {
+        PngFilter::None => png::Filter::NoFilter,
+        PngFilter::Sub => png::Filter::Sub,
+        PngFilter::Up => png::Filter::Up,
+        PngFilter::Avg => png::Filter::Avg,
+        PngFilter::Paeth => png::Filter::Paeth,
+        PngFilter::Adaptive => png::Filter::Adaptive,
+    });
+    encoder.write_header()?.write_image_data(data)
 }
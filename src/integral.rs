@@ -0,0 +1,243 @@
+use crate::border::{self, BorderMode};
+use crate::im::{self, Light};
+use image::Rgb;
+
+/// summed-area table of an rgb buffer, any rectangle sum costs four lookups
+pub struct IntegralImage {
+    width: u32,
+    /// `(width + 1) * (height + 1)` entries with a zero first row and column
+    sums: Vec<[u64; 3]>,
+}
+
+impl IntegralImage {
+    pub fn new<P: Light>(buffer: &image::ImageBuffer<Rgb<P>, Vec<P>>) -> IntegralImage
+    where
+        Rgb<P>: image::Pixel<Subpixel = P>,
+    {
+        IntegralImage::from_values(buffer, |value| value.into())
+    }
+
+    /// table of squared values, variances follow from it and `new`
+    pub fn new_squared<P: Light>(buffer: &image::ImageBuffer<Rgb<P>, Vec<P>>) -> IntegralImage
+    where
+        Rgb<P>: image::Pixel<Subpixel = P>,
+    {
+        IntegralImage::from_values(buffer, |value| {
+            let value: u64 = value.into();
+            value * value
+        })
+    }
+
+    fn from_values<P: Light>(
+        buffer: &image::ImageBuffer<Rgb<P>, Vec<P>>,
+        value: impl Fn(P) -> u64,
+    ) -> IntegralImage
+    where
+        Rgb<P>: image::Pixel<Subpixel = P>,
+    {
+        let (width, height) = buffer.dimensions();
+        let stride = width as usize + 1;
+        let mut sums = vec![[0u64; 3]; stride * (height as usize + 1)];
+
+        for y in 0..height as usize {
+            let mut row_sum = [0u64; 3];
+            for x in 0..width as usize {
+                let pixel = buffer.get_pixel(x as u32, y as u32);
+                for c in 0..3 {
+                    row_sum[c] += value(pixel[c]);
+                }
+                let above = sums[y * stride + x + 1];
+                sums[(y + 1) * stride + x + 1] = [
+                    above[0] + row_sum[0],
+                    above[1] + row_sum[1],
+                    above[2] + row_sum[2],
+                ];
+            }
+        }
+
+        IntegralImage { width, sums }
+    }
+
+    /// per channel sum over `x0..x1` and `y0..y1`, upper bounds exclusive
+    pub fn sum(&self, x0: u32, y0: u32, x1: u32, y1: u32) -> [u64; 3] {
+        let stride = self.width as usize + 1;
+        let at = |x: u32, y: u32| self.sums[y as usize * stride + x as usize];
+        let (a, b, c, d) = (at(x0, y0), at(x1, y0), at(x0, y1), at(x1, y1));
+        [
+            d[0] + a[0] - b[0] - c[0],
+            d[1] + a[1] - b[1] - c[1],
+            d[2] + a[2] - b[2] - c[2],
+        ]
+    }
+
+    /// per channel integer mean over `x0..x1` and `y0..y1`, upper bounds exclusive
+    pub fn mean<P: Light>(&self, x0: u32, y0: u32, x1: u32, y1: u32) -> Rgb<P> {
+        let area = ((x1 - x0) as u64 * (y1 - y0) as u64).max(1);
+        let sum = self.sum(x0, y0, x1, y1);
+        Rgb(sum.map(|s| P::from(s / area).expect("mean fits the channel type")))
+    }
+}
+
+/// mean over a `box_width` x `box_height` window around each pixel, outside
+/// pixels follow `border`, each pixel costs four summed-area table lookups
+pub fn image_set_pixels_to_box_mean(
+    image: image::DynamicImage,
+    box_width: u32,
+    box_height: u32,
+    border: BorderMode,
+    linear: bool,
+) -> image::ImageBuffer<Rgb<u8>, Vec<u8>> {
+    assert!(
+        box_width % 2 == 1 && box_height % 2 == 1,
+        "box sizes must be odd (eg: 1, 3, 5, 7..)"
+    );
+    let buffer = image.into_rgb8();
+    if linear {
+        let linear = im::buffer_into_linear(&buffer);
+        im::buffer_from_linear(&box_mean(linear, box_width, box_height, border))
+    } else {
+        box_mean(buffer, box_width, box_height, border)
+    }
+}
+
+fn box_mean<P: Light>(
+    mut buffer: image::ImageBuffer<Rgb<P>, Vec<P>>,
+    box_width: u32,
+    box_height: u32,
+    border: BorderMode,
+) -> image::ImageBuffer<Rgb<P>, Vec<P>>
+where
+    Rgb<P>: image::Pixel<Subpixel = P>,
+{
+    let (width, height) = buffer.dimensions();
+    let x_offset = box_width / 2;
+    let y_offset = box_height / 2;
+
+    if let BorderMode::Shrink = border {
+        let integral = IntegralImage::new(&buffer);
+        for (x, y, pixel) in buffer.enumerate_pixels_mut() {
+            let x_lb = x.saturating_sub(x_offset);
+            let x_rb = (x + x_offset + 1).min(width);
+            let y_tb = y.saturating_sub(y_offset);
+            let y_bb = (y + y_offset + 1).min(height);
+            *pixel = integral.mean(x_lb, y_tb, x_rb, y_bb);
+        }
+    } else {
+        // padded by the window offsets, so every window is full sized
+        let padded = border::pad(&buffer, x_offset, y_offset, x_offset, y_offset, border);
+        let integral = IntegralImage::new(&padded);
+        for (x, y, pixel) in buffer.enumerate_pixels_mut() {
+            *pixel = integral.mean(x, y, x + box_width, y + box_height);
+        }
+    }
+    buffer
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    type RgbBuffer = image::ImageBuffer<Rgb<u8>, Vec<u8>>;
+
+    pub(crate) const MODES: [BorderMode; 5] = [
+        BorderMode::Clamp,
+        BorderMode::Reflect,
+        BorderMode::Wrap,
+        BorderMode::Constant(Rgb([10, 200, 30])),
+        BorderMode::Shrink,
+    ];
+
+    /// odd, non-square and smaller than the larger boxes
+    pub(crate) const IMAGE_SIZES: [(u32, u32); 4] = [(7, 5), (4, 9), (3, 3), (1, 1)];
+    const BOX_SIZES: [(u32, u32); 5] = [(1, 1), (3, 3), (5, 3), (1, 7), (11, 9)];
+
+    /// deterministic noise, every channel differs from its neighbours
+    pub(crate) fn test_image(width: u32, height: u32) -> RgbBuffer {
+        RgbBuffer::from_fn(width, height, |x, y| {
+            Rgb([0u32, 1, 2]
+                .map(|c| ((x * 73 + y * 151 + c * 89).wrapping_mul(2_654_435_761) >> 24) as u8))
+        })
+    }
+
+    /// the nested loop mean the summed-area table replaced, extended with
+    /// the border modes
+    fn nested_loop_box_mean<P: Light>(
+        buffer: &image::ImageBuffer<Rgb<P>, Vec<P>>,
+        box_width: u32,
+        box_height: u32,
+        border: BorderMode,
+    ) -> image::ImageBuffer<Rgb<P>, Vec<P>>
+    where
+        Rgb<P>: image::Pixel<Subpixel = P>,
+    {
+        let (x_offset, y_offset) = ((box_width / 2) as i64, (box_height / 2) as i64);
+        image::ImageBuffer::from_fn(buffer.width(), buffer.height(), |x, y| {
+            let mut sum = [0u64; 3];
+            let mut count = 0u64;
+            for ys in y as i64 - y_offset..=y as i64 + y_offset {
+                for xs in x as i64 - x_offset..=x as i64 + x_offset {
+                    if let Some(pixel) = border.pixel(buffer, xs, ys) {
+                        for c in 0..3 {
+                            sum[c] += pixel[c].into();
+                        }
+                        count += 1;
+                    }
+                }
+            }
+            Rgb(sum.map(|s| P::from(s / count).unwrap()))
+        })
+    }
+
+    #[test]
+    fn sum_matches_direct_sum() {
+        let buffer = test_image(7, 5);
+        let integral = IntegralImage::new(&buffer);
+        let squares = IntegralImage::new_squared(&buffer);
+        let (mut sum, mut square_sum) = ([0u64; 3], [0u64; 3]);
+        for y in 1..4 {
+            for x in 2..7 {
+                for c in 0..3 {
+                    let value = buffer.get_pixel(x, y)[c] as u64;
+                    sum[c] += value;
+                    square_sum[c] += value * value;
+                }
+            }
+        }
+        assert_eq!(integral.sum(2, 1, 7, 4), sum);
+        assert_eq!(squares.sum(2, 1, 7, 4), square_sum);
+        assert_eq!(integral.sum(3, 2, 3, 2), [0; 3]);
+    }
+
+    #[test]
+    fn box_mean_matches_nested_loops() {
+        for border in MODES {
+            for (width, height) in IMAGE_SIZES {
+                let buffer = test_image(width, height);
+                for (box_width, box_height) in BOX_SIZES {
+                    let image = image::DynamicImage::ImageRgb8(buffer.clone());
+                    assert!(
+                        image_set_pixels_to_box_mean(image, box_width, box_height, border, false)
+                            == nested_loop_box_mean(&buffer, box_width, box_height, border),
+                        "{}x{} box on {}x{} image with {:?} border",
+                        box_width,
+                        box_height,
+                        width,
+                        height,
+                        border
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn linear_box_mean_matches_nested_loops() {
+        let buffer = im::buffer_into_linear(&test_image(7, 5));
+        for border in MODES {
+            assert!(
+                box_mean(buffer.clone(), 5, 3, border)
+                    == nested_loop_box_mean(&buffer, 5, 3, border)
+            );
+        }
+    }
+}
@@ -0,0 +1,455 @@
+//! image processing algorithms over `image` buffers
+//!
+//! the functions and option structs at the crate root are the stable api,
+//! invalid options are reported as [`Error::InvalidParameter`] instead of
+//! panicking
+//!
+//! the default `cli` feature builds the command line and pulls in `clap`,
+//! depend with `default-features = false` to use the algorithms alone
+
+// the batch runners are only reached from the command line
+#![cfg_attr(not(feature = "cli"), allow(dead_code))]
+
+use image::{GrayImage, RgbImage, Rgba, RgbaImage};
+
+#[allow(unused)]
+mod ansi;
+mod background;
+mod border;
+#[cfg(feature = "cli")]
+mod cli;
+mod convolve;
+mod dither;
+mod draw;
+mod error;
+mod evaluate;
+mod geometry;
+mod histogram;
+mod im;
+mod integral;
+mod metrics;
+mod progress;
+mod quantize;
+mod smooth;
+mod task_1;
+mod task_2;
+mod task_3;
+
+pub use border::BorderMode;
+pub use dither::{DiffusionKernel, Dither, ThresholdMap};
+pub use error::{Error, Result};
+pub use geometry::{Canvas, Interpolation};
+pub use im::{GrayMethod, Rect};
+pub use metrics::{compare, Metrics};
+pub use quantize::{PaletteMethod, Quantized};
+pub use task_1::Projections;
+pub use task_3::LevelScale;
+
+/// entry point of the `image_alg` binary, not part of the library api
+#[cfg(feature = "cli")]
+#[doc(hidden)]
+pub fn cli_main() {
+    cli::main()
+}
+
+/// options of [`threshold`]
+#[derive(Copy, Clone)]
+pub struct ThresholdOptions {
+    /// how colors are reduced to the intensity compared against `level`
+    pub gray: GrayMethod,
+    /// intensity in `0.0..=1.0` from which pixels become white
+    pub level: f32,
+}
+
+impl Default for ThresholdOptions {
+    fn default() -> ThresholdOptions {
+        ThresholdOptions {
+            gray: GrayMethod::Average,
+            level: 0.5,
+        }
+    }
+}
+
+/// binary mask, white where the gray intensity reaches the level
+pub fn threshold(buffer: &RgbImage, options: &ThresholdOptions) -> Result<GrayImage> {
+    if !(0.0..=1.0).contains(&options.level) {
+        return Err(Error::InvalidParameter(format!(
+            "threshold level {} is outside 0..=1",
+            options.level
+        )));
+    }
+    Ok(task_1::threshold(buffer, options.gray, options.level))
+}
+
+/// options of [`analyze_projections`]
+#[derive(Copy, Clone)]
+pub struct ProjectionOptions {
+    pub gray: GrayMethod,
+    /// rows or columns averaged into each profile entry, at least 1
+    pub smoothing: usize,
+    /// minimas kept per axis, the deepest ones first
+    pub minima_count: usize,
+}
+
+impl Default for ProjectionOptions {
+    fn default() -> ProjectionOptions {
+        ProjectionOptions {
+            gray: GrayMethod::Bt601,
+            smoothing: 4,
+            minima_count: 3,
+        }
+    }
+}
+
+/// row and column intensity profiles and the cells between their deepest
+/// minimas, used to split scans of several objects on a plain background
+pub fn analyze_projections(buffer: &RgbImage, options: &ProjectionOptions) -> Result<Projections> {
+    if options.smoothing == 0 {
+        return Err(Error::InvalidParameter(
+            "projection smoothing must be at least 1".to_string(),
+        ));
+    }
+    let gray = task_1::grayscale(buffer, options.gray);
+    Ok(task_1::project(
+        &gray,
+        options.smoothing,
+        options.minima_count,
+    ))
+}
+
+/// options of [`carve_seams`]
+#[derive(Copy, Clone)]
+pub struct SeamCarveOptions {
+    /// vertical seams removed, below the image width
+    pub columns: u32,
+}
+
+/// content aware narrowing, removes the lowest energy vertical seams of the
+/// sobel gradient one at a time
+pub fn carve_seams(buffer: &RgbImage, options: &SeamCarveOptions) -> Result<RgbImage> {
+    let image = image::DynamicImage::ImageRgb8(buffer.clone());
+    task_2::carve(image, options.columns, false).map(|carved| carved.image)
+}
+
+/// options of [`rotate`]
+#[derive(Copy, Clone)]
+pub struct RotateOptions {
+    /// clockwise angle
+    pub degrees: f32,
+    pub interpolation: Interpolation,
+    pub canvas: Canvas,
+    /// color of the uncovered canvas
+    pub fill: Rgba<u8>,
+    /// interpolate in linear light instead of sRGB bytes
+    pub linear: bool,
+}
+
+impl Default for RotateOptions {
+    fn default() -> RotateOptions {
+        RotateOptions {
+            degrees: 0.0,
+            interpolation: Interpolation::Bilinear,
+            canvas: Canvas::Expand,
+            fill: Rgba([0, 0, 0, 255]),
+            linear: false,
+        }
+    }
+}
+
+/// rotation about the image center
+pub fn rotate(buffer: &RgbaImage, options: &RotateOptions) -> Result<RgbaImage> {
+    geometry::rotate(
+        buffer,
+        options.degrees,
+        options.interpolation,
+        options.canvas,
+        options.fill,
+        options.linear,
+    )
+}
+
+/// options of [`quantize`]
+pub struct QuantizeOptions {
+    /// palette size in `2..=256`
+    pub colors: usize,
+    pub method: PaletteMethod,
+    pub dither: Dither,
+}
+
+impl Default for QuantizeOptions {
+    fn default() -> QuantizeOptions {
+        QuantizeOptions {
+            colors: 16,
+            method: PaletteMethod::MedianCut,
+            dither: Dither::None,
+        }
+    }
+}
+
+/// palette of at most `colors` entries and the palette index of each pixel
+pub fn quantize(buffer: &RgbImage, options: &QuantizeOptions) -> Result<Quantized> {
+    if !(2..=256).contains(&options.colors) {
+        return Err(Error::InvalidParameter(format!(
+            "palette size {} is outside 2..=256",
+            options.colors
+        )));
+    }
+    Ok(quantize::quantize(
+        buffer,
+        options.colors,
+        options.method,
+        &options.dither,
+    ))
+}
+
+/// options of [`reduce_intensity_levels`]
+pub struct LevelOptions {
+    /// levels per channel in `2..=256`
+    pub levels: u32,
+    pub scale: LevelScale,
+    pub dither: Dither,
+}
+
+impl Default for LevelOptions {
+    fn default() -> LevelOptions {
+        LevelOptions {
+            levels: 4,
+            scale: LevelScale::Uniform,
+            dither: Dither::None,
+        }
+    }
+}
+
+/// per channel quantization to a fixed number of intensity levels
+pub fn reduce_intensity_levels(buffer: &RgbImage, options: &LevelOptions) -> Result<RgbImage> {
+    if !(2..=256).contains(&options.levels) {
+        return Err(Error::InvalidParameter(format!(
+            "level count {} is outside 2..=256",
+            options.levels
+        )));
+    }
+    Ok(task_3::intensity_levels(
+        buffer,
+        options.levels,
+        options.scale,
+        &options.dither,
+    ))
+}
+
+/// where [`block_mean`] averages
+#[derive(Copy, Clone)]
+pub enum BlockMeanMode {
+    /// every pixel becomes the mean of the block centered on it, block sizes
+    /// must be odd
+    Window,
+    /// the image is tiled into blocks that are each set to their mean
+    Region,
+}
+
+/// options of [`block_mean`]
+#[derive(Copy, Clone)]
+pub struct BlockMeanOptions {
+    pub width: u32,
+    pub height: u32,
+    pub mode: BlockMeanMode,
+    /// how blocks reaching past the image edge are averaged
+    pub border: BorderMode,
+    /// average in linear light instead of sRGB bytes
+    pub linear: bool,
+}
+
+impl Default for BlockMeanOptions {
+    fn default() -> BlockMeanOptions {
+        BlockMeanOptions {
+            width: 3,
+            height: 3,
+            mode: BlockMeanMode::Window,
+            border: BorderMode::Shrink,
+            linear: false,
+        }
+    }
+}
+
+/// box blur or pixelation, both backed by a summed-area table
+pub fn block_mean(buffer: &RgbImage, options: &BlockMeanOptions) -> Result<RgbImage> {
+    let (width, height) = (options.width, options.height);
+    let image = image::DynamicImage::ImageRgb8(buffer.clone());
+    match options.mode {
+        BlockMeanMode::Window if width % 2 == 0 || height % 2 == 0 => Err(Error::InvalidParameter(
+            format!("window {}x{} must have odd sizes", width, height),
+        )),
+        BlockMeanMode::Window => Ok(integral::image_set_pixels_to_box_mean(
+            image,
+            width,
+            height,
+            options.border,
+            options.linear,
+        )),
+        BlockMeanMode::Region if width == 0 || height == 0 => Err(Error::InvalidParameter(
+            format!("block {}x{} must have positive sizes", width, height),
+        )),
+        BlockMeanMode::Region => Ok(task_3::image_set_region_to_block_mean(
+            image,
+            width,
+            height,
+            options.border,
+            options.linear,
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgb;
+
+    fn row(values: &[u8]) -> RgbImage {
+        RgbImage::from_fn(values.len() as u32, 1, |x, _| Rgb([values[x as usize]; 3]))
+    }
+
+    fn is_invalid<T>(result: Result<T>) -> bool {
+        matches!(result, Err(Error::InvalidParameter(_)))
+    }
+
+    #[test]
+    fn threshold_splits_at_level() {
+        let options = ThresholdOptions {
+            level: 1.5,
+            ..Default::default()
+        };
+        assert!(is_invalid(threshold(&row(&[0]), &options)));
+
+        let mask = threshold(&row(&[0, 127, 128, 255]), &ThresholdOptions::default()).unwrap();
+        assert_eq!(mask.into_raw(), vec![0, 0, 255, 255]);
+    }
+
+    #[test]
+    fn projections_split_at_dark_column() {
+        let buffer = RgbImage::from_fn(8, 4, |x, _| Rgb([if x == 4 { 0 } else { 255 }; 3]));
+        let options = ProjectionOptions {
+            smoothing: 0,
+            ..Default::default()
+        };
+        assert!(is_invalid(analyze_projections(&buffer, &options)));
+
+        let options = ProjectionOptions {
+            smoothing: 1,
+            minima_count: 1,
+            ..Default::default()
+        };
+        let projections = analyze_projections(&buffer, &options).unwrap();
+        assert_eq!(
+            projections.columns,
+            vec![4.0, 4.0, 4.0, 4.0, 0.0, 4.0, 4.0, 4.0]
+        );
+        assert_eq!(projections.column_minimas, vec![4]);
+        // the flat row profile may split rows anywhere, columns split at the dip
+        assert!(projections
+            .bounds
+            .iter()
+            .all(|b| (b.x, b.width) == (0, 4) || (b.x, b.width) == (4, 4)));
+    }
+
+    #[test]
+    fn carve_seams_narrows_the_image() {
+        let buffer = RgbImage::from_pixel(4, 3, Rgb([10, 20, 30]));
+        assert!(is_invalid(carve_seams(
+            &buffer,
+            &SeamCarveOptions { columns: 4 }
+        )));
+
+        let carved = carve_seams(&buffer, &SeamCarveOptions { columns: 1 }).unwrap();
+        assert_eq!(carved, RgbImage::from_pixel(3, 3, Rgb([10, 20, 30])));
+    }
+
+    #[test]
+    fn rotate_quarter_turn_is_exact() {
+        let buffer = RgbaImage::from_fn(2, 1, |x, _| {
+            Rgba(if x == 0 {
+                [255, 0, 0, 255]
+            } else {
+                [0, 0, 255, 255]
+            })
+        });
+        let options = RotateOptions {
+            degrees: f32::NAN,
+            ..Default::default()
+        };
+        assert!(is_invalid(rotate(&buffer, &options)));
+
+        let options = RotateOptions {
+            degrees: 90.0,
+            interpolation: Interpolation::Nearest,
+            ..Default::default()
+        };
+        let rotated = rotate(&buffer, &options).unwrap();
+        assert_eq!(rotated.dimensions(), (1, 2));
+        assert_eq!(rotated.into_raw(), vec![255, 0, 0, 255, 0, 0, 255, 255]);
+    }
+
+    #[test]
+    fn quantize_keeps_two_colors() {
+        let buffer = row(&[0, 0, 255, 255]);
+        let options = QuantizeOptions {
+            colors: 1,
+            ..Default::default()
+        };
+        assert!(is_invalid(quantize(&buffer, &options)));
+
+        let options = QuantizeOptions {
+            colors: 2,
+            ..Default::default()
+        };
+        let quantized = quantize(&buffer, &options).unwrap();
+        let colors: Vec<Rgb<u8>> = quantized
+            .indices
+            .iter()
+            .map(|&idx| quantized.palette[idx as usize])
+            .collect();
+        assert_eq!(
+            colors,
+            row(&[0, 0, 255, 255]).pixels().copied().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn two_levels_are_bin_centers() {
+        let buffer = row(&[0, 100, 200, 255]);
+        let options = LevelOptions {
+            levels: 1,
+            ..Default::default()
+        };
+        assert!(is_invalid(reduce_intensity_levels(&buffer, &options)));
+
+        let options = LevelOptions {
+            levels: 2,
+            ..Default::default()
+        };
+        let reduced = reduce_intensity_levels(&buffer, &options).unwrap();
+        assert_eq!(reduced, row(&[64, 64, 192, 192]));
+    }
+
+    #[test]
+    fn block_mean_windows_and_regions() {
+        let buffer = row(&[0, 30, 60, 90]);
+        let options = |width, mode| BlockMeanOptions {
+            width,
+            height: 1,
+            mode,
+            ..Default::default()
+        };
+        assert!(is_invalid(block_mean(
+            &buffer,
+            &options(2, BlockMeanMode::Window)
+        )));
+        assert!(is_invalid(block_mean(
+            &buffer,
+            &options(0, BlockMeanMode::Region)
+        )));
+
+        let window = block_mean(&buffer, &options(3, BlockMeanMode::Window)).unwrap();
+        assert_eq!(window, row(&[15, 30, 60, 75]));
+        let region = block_mean(&buffer, &options(2, BlockMeanMode::Region)).unwrap();
+        assert_eq!(region, row(&[15, 15, 75, 75]));
+    }
+}
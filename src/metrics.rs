@@ -0,0 +1,346 @@
+use crate::convolve;
+use crate::error::{self, Error};
+use crate::im::{self, Lab, RgbF};
+use crate::progress;
+use image::Rgb;
+use std::path::Path;
+
+type RgbBuffer = image::ImageBuffer<Rgb<u8>, Vec<u8>>;
+
+/// full reference quality of a processed image against its original
+pub struct Metrics {
+    pub mse: f64,
+    /// infinite for identical images
+    pub psnr: f64,
+    /// structural similarity of the gray intensity, 1 for identical images
+    pub ssim: f64,
+    /// five scale SSIM, fewer scales for small images
+    pub ms_ssim: f64,
+    pub delta_e_mean: f64,
+    pub delta_e_max: f64,
+}
+
+// constants taken from: Wang et al. "Image Quality Assessment: From Error
+// Visibility to Structural Similarity" and "Multi-scale structural similarity"
+const SSIM_SIGMA: f32 = 1.5;
+const SSIM_K1: f64 = 0.01;
+const SSIM_K2: f64 = 0.03;
+const MS_SSIM_WEIGHTS: [f64; 5] = [0.0448, 0.2856, 0.3001, 0.2363, 0.1333];
+
+/// CIEDE2000 difference at which the heatmap saturates
+const HEATMAP_MAX_DELTA_E: f32 = 10.0;
+
+pub fn run(
+    batch: &im::Batch,
+    reference: &Path,
+    processed: &Path,
+    gray: im::GrayMethod,
+) -> error::Result<()> {
+    let processed_image = im::image_open(processed, batch.exif_orientation)?.into_rgb8();
+    let processed_name = processed
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let results = [format!("vs_{processed_name}_heatmap")];
+    im::for_each_image(
+        batch,
+        &[reference.to_path_buf()],
+        &results,
+        |image, info| {
+            let original = image.into_rgb8();
+            let metrics = compare(&original, &processed_image, gray)?;
+            progress::println(format!(
+            "compared: `{}` to `{}`\n  mse {:.3}\n  psnr {:.2} dB\n  ssim {:.4}\n  ms-ssim {:.4}\n  ciede2000 mean {:.3}, max {:.3}",
+            info.name(),
+            processed_name,
+            metrics.mse,
+            metrics.psnr,
+            metrics.ssim,
+            metrics.ms_ssim,
+            metrics.delta_e_mean,
+            metrics.delta_e_max
+        ));
+            im::image_buffer_save(
+                error_heatmap(&original, &processed_image),
+                &info.save_path_concat(&results[0]),
+            )
+        },
+    )
+}
+
+/// every metric at once, images must have the same size
+pub fn compare(
+    original: &RgbBuffer,
+    processed: &RgbBuffer,
+    gray: im::GrayMethod,
+) -> error::Result<Metrics> {
+    if original.dimensions() != processed.dimensions() {
+        return Err(Error::InvalidParameter(format!(
+            "image sizes differ, {}x{} and {}x{}",
+            original.width(),
+            original.height(),
+            processed.width(),
+            processed.height()
+        )));
+    }
+    let mse = mse(original, processed);
+    let (delta_e_mean, delta_e_max) = ciede2000(original, processed);
+    Ok(Metrics {
+        mse,
+        psnr: psnr_from_mse(mse),
+        ssim: ssim(original, processed, gray),
+        ms_ssim: ms_ssim(original, processed, gray),
+        delta_e_mean,
+        delta_e_max,
+    })
+}
+
+/// mean squared error over all channels
+pub fn mse(original: &RgbBuffer, processed: &RgbBuffer) -> f64 {
+    let squared_sum: f64 = original
+        .as_raw()
+        .iter()
+        .zip(processed.as_raw().iter())
+        .map(|(&a, &b)| (a as f64 - b as f64).powi(2))
+        .sum();
+    squared_sum / (original.as_raw().len() as f64).max(1.0)
+}
+
+pub fn psnr_from_mse(mse: f64) -> f64 {
+    if mse == 0.0 {
+        f64::INFINITY
+    } else {
+        10.0 * (255.0f64.powi(2) / mse).log10()
+    }
+}
+
+pub fn ssim(original: &RgbBuffer, processed: &RgbBuffer, gray: im::GrayMethod) -> f64 {
+    let (width, height) = (original.width() as usize, original.height() as usize);
+    ssim_components(&luma(original, gray), &luma(processed, gray), width, height).0
+}
+
+/// Wang's multi scale SSIM, contrast and structure at every 2x downsampled
+/// scale, full SSIM at the coarsest one
+pub fn ms_ssim(original: &RgbBuffer, processed: &RgbBuffer, gray: im::GrayMethod) -> f64 {
+    let (mut width, mut height) = (original.width() as usize, original.height() as usize);
+    let window = convolve::gaussian_radius(SSIM_SIGMA) * 2 + 1;
+    let mut scales = 1;
+    while scales < MS_SSIM_WEIGHTS.len() && (width >> scales).min(height >> scales) >= window {
+        scales += 1;
+    }
+    let weights = &MS_SSIM_WEIGHTS[..scales];
+    let weight_sum: f64 = weights.iter().sum();
+
+    let (mut a, mut b) = (luma(original, gray), luma(processed, gray));
+    let mut result = 1.0;
+    for (scale, weight) in weights.iter().enumerate() {
+        let (ssim, contrast_structure) = ssim_components(&a, &b, width, height);
+        let weight = weight / weight_sum;
+        if scale + 1 == scales {
+            result *= ssim.max(0.0).powf(weight);
+        } else {
+            result *= contrast_structure.max(0.0).powf(weight);
+            a = downsample(&a, width, height);
+            b = downsample(&b, width, height);
+            (width, height) = (width / 2, height / 2);
+        }
+    }
+    result
+}
+
+/// mean and max CIEDE2000 color difference
+pub fn ciede2000(original: &RgbBuffer, processed: &RgbBuffer) -> (f64, f64) {
+    let (sum, max) =
+        original
+            .pixels()
+            .zip(processed.pixels())
+            .fold((0.0f64, 0.0f64), |(sum, max), (a, b)| {
+                let delta = delta_e(*a, *b) as f64;
+                (sum + delta, max.max(delta))
+            });
+    let pixel_count = (original.width() as f64 * original.height() as f64).max(1.0);
+    (sum / pixel_count, max)
+}
+
+/// per pixel CIEDE2000 on a black, blue, red, yellow, white ramp
+pub fn error_heatmap(original: &RgbBuffer, processed: &RgbBuffer) -> RgbBuffer {
+    const RAMP: [[f32; 3]; 5] = [
+        [0.0, 0.0, 0.0],
+        [0.0, 0.0, 255.0],
+        [255.0, 0.0, 0.0],
+        [255.0, 255.0, 0.0],
+        [255.0, 255.0, 255.0],
+    ];
+    RgbBuffer::from_fn(original.width(), original.height(), |x, y| {
+        let delta = delta_e(*original.get_pixel(x, y), *processed.get_pixel(x, y));
+        let t = (delta / HEATMAP_MAX_DELTA_E).clamp(0.0, 1.0) * (RAMP.len() - 1) as f32;
+        let idx = (t.floor() as usize).min(RAMP.len() - 2);
+        let frac = t - idx as f32;
+        let (from, to) = (RAMP[idx], RAMP[idx + 1]);
+        Rgb([0, 1, 2].map(|c| (from[c] + (to[c] - from[c]) * frac).round() as u8))
+    })
+}
+
+fn delta_e(a: Rgb<u8>, b: Rgb<u8>) -> f32 {
+    if a == b {
+        return 0.0;
+    }
+    Lab::from_rgb(RgbF::from_u8(a)).ciede2000(&Lab::from_rgb(RgbF::from_u8(b)))
+}
+
+/// gray intensity in 0..255 units
+fn luma(buffer: &RgbBuffer, gray: im::GrayMethod) -> Vec<f64> {
+    buffer
+        .pixels()
+        .map(|&p| gray.intensity(RgbF::from_u8(p)) as f64 * 255.0)
+        .collect()
+}
+
+/// 2x2 box downsample, a trailing odd row or column is dropped
+fn downsample(plane: &[f64], width: usize, height: usize) -> Vec<f64> {
+    let (w, h) = (width / 2, height / 2);
+    let mut output = Vec::with_capacity(w * h);
+    for y in 0..h {
+        for x in 0..w {
+            let at = |dx: usize, dy: usize| plane[(y * 2 + dy) * width + x * 2 + dx];
+            output.push((at(0, 0) + at(1, 0) + at(0, 1) + at(1, 1)) / 4.0);
+        }
+    }
+    output
+}
+
+/// mean of the SSIM map and of its contrast structure term
+fn ssim_components(a: &[f64], b: &[f64], width: usize, height: usize) -> (f64, f64) {
+    let c1 = (SSIM_K1 * 255.0).powi(2);
+    let c2 = (SSIM_K2 * 255.0).powi(2);
+    let blur = |plane: Vec<f64>| gaussian_blur(&plane, width, height);
+    let mu_a = blur(a.to_vec());
+    let mu_b = blur(b.to_vec());
+    let aa = blur(a.iter().map(|v| v * v).collect());
+    let bb = blur(b.iter().map(|v| v * v).collect());
+    let ab = blur(a.iter().zip(b).map(|(x, y)| x * y).collect());
+
+    let (mut ssim, mut contrast_structure) = (0.0, 0.0);
+    for idx in 0..a.len() {
+        let (ma, mb) = (mu_a[idx], mu_b[idx]);
+        let var_a = aa[idx] - ma * ma;
+        let var_b = bb[idx] - mb * mb;
+        let covariance = ab[idx] - ma * mb;
+        let luminance = (2.0 * ma * mb + c1) / (ma * ma + mb * mb + c1);
+        let cs = (2.0 * covariance + c2) / (var_a + var_b + c2);
+        ssim += luminance * cs;
+        contrast_structure += cs;
+    }
+    let count = a.len().max(1) as f64;
+    (ssim / count, contrast_structure / count)
+}
+
+/// separable SSIM window blur, edge pixels are repeated
+fn gaussian_blur(plane: &[f64], width: usize, height: usize) -> Vec<f64> {
+    let radius = convolve::gaussian_radius(SSIM_SIGMA);
+    let taps: Vec<f64> = convolve::gaussian_weights(SSIM_SIGMA, radius)
+        .into_iter()
+        .map(|w| w as f64)
+        .collect();
+    let pass = |source: &[f64], horizontal: bool| -> Vec<f64> {
+        let mut output = vec![0.0; source.len()];
+        for y in 0..height {
+            for x in 0..width {
+                output[y * width + x] = taps
+                    .iter()
+                    .enumerate()
+                    .map(|(k, w)| {
+                        let offset = k as i64 - radius as i64;
+                        let idx = if horizontal {
+                            let xs = (x as i64 + offset).clamp(0, width as i64 - 1) as usize;
+                            y * width + xs
+                        } else {
+                            let ys = (y as i64 + offset).clamp(0, height as i64 - 1) as usize;
+                            ys * width + x
+                        };
+                        source[idx] * w
+                    })
+                    .sum();
+            }
+        }
+        output
+    };
+    pass(&pass(plane, true), false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ciede2000_matches_sharma_reference_pairs() {
+        // test data taken from: Sharma, Wu, Dalal "The CIEDE2000
+        // Color-Difference Formula", table 1
+        #[rustfmt::skip]
+        let pairs: [([f32; 3], [f32; 3], f32); 34] = [
+            ([50.0, 2.6772, -79.7751], [50.0, 0.0, -82.7485], 2.0425),
+            ([50.0, 3.1571, -77.2803], [50.0, 0.0, -82.7485], 2.8615),
+            ([50.0, 2.8361, -74.0200], [50.0, 0.0, -82.7485], 3.4412),
+            ([50.0, -1.3802, -84.2814], [50.0, 0.0, -82.7485], 1.0000),
+            ([50.0, -1.1848, -84.8006], [50.0, 0.0, -82.7485], 1.0000),
+            ([50.0, -0.9009, -85.5211], [50.0, 0.0, -82.7485], 1.0000),
+            ([50.0, 0.0, 0.0], [50.0, -1.0, 2.0], 2.3669),
+            ([50.0, -1.0, 2.0], [50.0, 0.0, 0.0], 2.3669),
+            ([50.0, 2.4900, -0.0010], [50.0, -2.4900, 0.0009], 7.1792),
+            ([50.0, 2.4900, -0.0010], [50.0, -2.4900, 0.0010], 7.1792),
+            ([50.0, 2.4900, -0.0010], [50.0, -2.4900, 0.0011], 7.2195),
+            ([50.0, 2.4900, -0.0010], [50.0, -2.4900, 0.0012], 7.2195),
+            ([50.0, -0.0010, 2.4900], [50.0, 0.0009, -2.4900], 4.8045),
+            ([50.0, -0.0010, 2.4900], [50.0, 0.0010, -2.4900], 4.8045),
+            ([50.0, -0.0010, 2.4900], [50.0, 0.0011, -2.4900], 4.7461),
+            ([50.0, 2.5, 0.0], [50.0, 0.0, -2.5], 4.3065),
+            ([50.0, 2.5, 0.0], [73.0, 25.0, -18.0], 27.1492),
+            ([50.0, 2.5, 0.0], [61.0, -5.0, 29.0], 22.8977),
+            ([50.0, 2.5, 0.0], [56.0, -27.0, -3.0], 31.9030),
+            ([50.0, 2.5, 0.0], [58.0, 24.0, 15.0], 19.4535),
+            ([50.0, 2.5, 0.0], [50.0, 3.1736, 0.5854], 1.0000),
+            ([50.0, 2.5, 0.0], [50.0, 3.2972, 0.0], 1.0000),
+            ([50.0, 2.5, 0.0], [50.0, 1.8634, 0.5757], 1.0000),
+            ([50.0, 2.5, 0.0], [50.0, 3.2592, 0.3350], 1.0000),
+            ([60.2574, -34.0099, 36.2677], [60.4626, -34.1751, 39.4387], 1.2644),
+            ([63.0109, -31.0961, -5.8663], [62.8187, -29.7946, -4.0864], 1.2630),
+            ([61.2901, 3.7196, -5.3901], [61.4292, 2.2480, -4.9620], 1.8731),
+            ([35.0831, -44.1164, 3.7933], [35.0232, -40.0716, 1.5901], 1.8645),
+            ([22.7233, 20.0904, -46.6940], [23.0331, 14.9730, -42.5619], 2.0373),
+            ([36.4612, 47.8580, 18.3852], [36.2715, 50.5065, 21.2231], 1.4146),
+            ([90.8027, -2.0831, 1.4410], [91.1528, -1.6435, 0.0447], 1.4441),
+            ([90.9257, -0.5406, -0.9208], [88.6381, -0.8985, -0.7239], 1.5381),
+            ([6.7747, -0.2908, -2.4247], [5.8714, -0.0985, -2.2286], 0.6377),
+            ([2.0776, 0.0795, -1.1350], [0.9033, -0.0636, -0.5514], 0.9082),
+        ];
+        let lab = |[l, a, b]: [f32; 3]| Lab { l, a, b };
+        for (first, second, expected) in pairs {
+            let delta = lab(first).ciede2000(&lab(second));
+            assert!(
+                (delta - expected).abs() < 1e-4,
+                "{first:?} {second:?} gave {delta}, expected {expected}"
+            );
+        }
+    }
+
+    #[test]
+    fn identical_images_score_perfectly() {
+        let buffer = crate::integral::tests::test_image(48, 40);
+        let metrics = compare(&buffer, &buffer, im::GrayMethod::Bt601).unwrap();
+        assert_eq!(metrics.mse, 0.0);
+        assert_eq!(metrics.psnr, f64::INFINITY);
+        assert!((metrics.ssim - 1.0).abs() < 1e-9);
+        assert!((metrics.ms_ssim - 1.0).abs() < 1e-9);
+        assert_eq!((metrics.delta_e_mean, metrics.delta_e_max), (0.0, 0.0));
+    }
+
+    #[test]
+    fn different_sizes_are_rejected() {
+        let a = crate::integral::tests::test_image(8, 8);
+        let b = crate::integral::tests::test_image(8, 7);
+        assert!(matches!(
+            compare(&a, &b, im::GrayMethod::Bt601),
+            Err(Error::InvalidParameter(_))
+        ));
+    }
+}
@@ -0,0 +1,61 @@
+use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
+use std::io::IsTerminal;
+use std::sync::Mutex;
+
+/// bar of the running batch, log lines are printed above it while shown
+static BAR: Mutex<Option<ProgressBar>> = Mutex::new(None);
+
+/// prepares a bar for a batch whose images are counted as they are found,
+/// it is drawn once there are two and only when both output streams are
+/// terminals, so redirected logs stay plain
+pub fn start() {
+    if !std::io::stdout().is_terminal() || !std::io::stderr().is_terminal() {
+        return;
+    }
+    let bar = ProgressBar::with_draw_target(Some(0), ProgressDrawTarget::hidden()).with_style(
+        ProgressStyle::with_template(
+            "{elapsed_precise} [{bar:40.cyan/blue}] {pos}/{len} images, eta {eta}",
+        )
+        .expect("progress template")
+        .progress_chars("=> "),
+    );
+    *BAR.lock().expect("progress lock") = Some(bar);
+}
+
+/// adds `count` newly found images to the batch length
+pub fn discovered(count: usize) {
+    if let Some(bar) = &*BAR.lock().expect("progress lock") {
+        bar.inc_length(count as u64);
+        if bar.is_hidden() && bar.length().unwrap_or(0) >= 2 {
+            bar.set_draw_target(ProgressDrawTarget::stderr());
+        }
+    }
+}
+
+pub fn advance() {
+    if let Some(bar) = &*BAR.lock().expect("progress lock") {
+        bar.inc(1);
+    }
+}
+
+pub fn finish() {
+    if let Some(bar) = BAR.lock().expect("progress lock").take() {
+        bar.finish_and_clear();
+    }
+}
+
+/// `println!` that keeps the bar below the line
+pub fn println(line: impl AsRef<str>) {
+    match &*BAR.lock().expect("progress lock") {
+        Some(bar) if !bar.is_hidden() => bar.println(line),
+        _ => println!("{}", line.as_ref()),
+    }
+}
+
+/// `eprintln!` that keeps the bar below the line
+pub fn eprintln(line: impl AsRef<str>) {
+    match &*BAR.lock().expect("progress lock") {
+        Some(bar) if !bar.is_hidden() => bar.println(line),
+        _ => eprintln!("{}", line.as_ref()),
+    }
+}
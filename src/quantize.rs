@@ -0,0 +1,727 @@
+use crate::dither::Dither;
+use crate::error;
+use crate::im::{self, Lab, RgbF};
+use crate::metrics;
+use crate::progress;
+use image::Rgb;
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+type RgbBuffer = image::ImageBuffer<Rgb<u8>, Vec<u8>>;
+
+#[derive(Copy, Clone)]
+pub enum PaletteMethod {
+    MedianCut,
+    Octree,
+    /// Xiaolin Wu's variance minimizing quantizer
+    Wu,
+    /// k-means clustering in CIE Lab, seeded with median cut
+    KMeans,
+}
+
+impl PaletteMethod {
+    pub const NAMES: &'static str = "median-cut, octree, wu, kmeans, all";
+    pub const ALL: [PaletteMethod; 4] = [
+        PaletteMethod::MedianCut,
+        PaletteMethod::Octree,
+        PaletteMethod::Wu,
+        PaletteMethod::KMeans,
+    ];
+
+    /// `all` yields every method
+    pub fn from_name(name: &str) -> Option<Vec<PaletteMethod>> {
+        let method = match name {
+            "median-cut" => PaletteMethod::MedianCut,
+            "octree" => PaletteMethod::Octree,
+            "wu" => PaletteMethod::Wu,
+            "kmeans" => PaletteMethod::KMeans,
+            "all" => return Some(PaletteMethod::ALL.to_vec()),
+            _ => return None,
+        };
+        Some(vec![method])
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            PaletteMethod::MedianCut => "median_cut",
+            PaletteMethod::Octree => "octree",
+            PaletteMethod::Wu => "wu",
+            PaletteMethod::KMeans => "kmeans",
+        }
+    }
+}
+
+pub struct Quantized {
+    pub width: u32,
+    pub height: u32,
+    /// palette index of each pixel in row major order
+    pub indices: Vec<u8>,
+    pub palette: Vec<Rgb<u8>>,
+}
+
+impl Quantized {
+    pub fn to_rgb(&self) -> RgbBuffer {
+        let mut buffer = RgbBuffer::new(self.width, self.height);
+        for (pixel, &idx) in buffer.pixels_mut().zip(self.indices.iter()) {
+            *pixel = self.palette[idx as usize];
+        }
+        buffer
+    }
+}
+
+pub struct QuantizationError {
+    pub mse: f64,
+    pub psnr: f64,
+    /// mean CIE76 color difference
+    pub mean_delta_e: f64,
+}
+
+pub fn run(
+    batch: &im::Batch,
+    targets: &[PathBuf],
+    colors: usize,
+    methods: &[PaletteMethod],
+    dither: &Dither,
+) -> error::Result<()> {
+    let postfix = |method: PaletteMethod| format!("{}_{colors}{}", method.name(), dither.postfix());
+    let results: Vec<String> = methods
+        .iter()
+        .flat_map(|&method| [postfix(method), format!("{}_swatch", postfix(method))])
+        .collect();
+    im::for_each_image(batch, targets, &results, |image, info| {
+        let buffer = image.into_rgb8();
+        for &method in methods {
+            let quantized = quantize(&buffer, colors, method, dither);
+            let error = measure_error(&buffer, &quantized.to_rgb());
+            progress::println(format!(
+                "quantized: `{}` {} to {} colors, mse {:.2}, psnr {:.2} dB, mean delta e {:.2}",
+                info.name(),
+                method.name(),
+                quantized.palette.len(),
+                error.mse,
+                error.psnr,
+                error.mean_delta_e
+            ));
+
+            let postfix = postfix(method);
+            im::image_indexed_save(
+                quantized.width,
+                quantized.height,
+                &quantized.indices,
+                &quantized.palette,
+                &info.save_path_concat(&postfix),
+            )?;
+            im::image_buffer_save(
+                render_swatch(&quantized.palette),
+                &info.save_path_concat(&format!("{postfix}_swatch")),
+            )?;
+        }
+        Ok(())
+    })
+}
+
+pub fn quantize(
+    buffer: &RgbBuffer,
+    colors: usize,
+    method: PaletteMethod,
+    dither: &Dither,
+) -> Quantized {
+    let palette = build_palette(buffer, colors, method);
+    let lab = matches!(method, PaletteMethod::KMeans);
+    Quantized {
+        width: buffer.width(),
+        height: buffer.height(),
+        indices: remap(buffer, &palette, lab, dither),
+        palette,
+    }
+}
+
+/// at most `colors` palette entries, fewer when the image has fewer colors
+pub fn build_palette(buffer: &RgbBuffer, colors: usize, method: PaletteMethod) -> Vec<Rgb<u8>> {
+    let histogram = color_histogram(buffer);
+    match method {
+        PaletteMethod::MedianCut => median_cut(&histogram, colors),
+        PaletteMethod::Octree => octree(&histogram, colors),
+        PaletteMethod::Wu => wu(&histogram, colors),
+        PaletteMethod::KMeans => kmeans_lab(&histogram, colors),
+    }
+}
+
+/// nearest palette entry of each pixel, in rgb or Lab distance, ordered
+/// dithering spreads by about the per channel spacing of the palette
+pub fn remap(buffer: &RgbBuffer, palette: &[Rgb<u8>], lab: bool, dither: &Dither) -> Vec<u8> {
+    let nearest = PaletteLookup::new(palette, lab);
+    let mut cache: HashMap<[u8; 3], u8> = HashMap::new();
+    let spread = 255.0 / (palette.len().max(1) as f32).cbrt();
+    dither.apply(buffer, spread, |value| {
+        let color = value.map(|v| v.round().clamp(0.0, 255.0) as u8);
+        let idx = *cache
+            .entry(color)
+            .or_insert_with(|| nearest.find(Rgb(color)));
+        (idx, palette[idx as usize].0.map(|c| c as f32))
+    })
+}
+
+/// nearest color search over a palette
+pub struct PaletteLookup {
+    palette: Vec<Rgb<u8>>,
+    palette_lab: Option<Vec<Lab>>,
+}
+
+impl PaletteLookup {
+    pub fn new(palette: &[Rgb<u8>], lab: bool) -> PaletteLookup {
+        let palette_lab = lab.then(|| {
+            palette
+                .iter()
+                .map(|&c| Lab::from_rgb(RgbF::from_u8(c)))
+                .collect()
+        });
+        PaletteLookup {
+            palette: palette.to_vec(),
+            palette_lab,
+        }
+    }
+
+    pub fn find(&self, color: Rgb<u8>) -> u8 {
+        let idx = match &self.palette_lab {
+            Some(palette_lab) => {
+                let lab = Lab::from_rgb(RgbF::from_u8(color));
+                palette_lab
+                    .iter()
+                    .enumerate()
+                    .min_by(|(_, a), (_, b)| {
+                        a.distance_squared(&lab)
+                            .total_cmp(&b.distance_squared(&lab))
+                    })
+                    .map(|(idx, _)| idx)
+            }
+            None => self
+                .palette
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, p)| {
+                    (0..3)
+                        .map(|c| (p[c] as i32 - color[c] as i32).pow(2))
+                        .sum::<i32>()
+                })
+                .map(|(idx, _)| idx),
+        };
+        idx.unwrap_or(0) as u8
+    }
+}
+
+pub fn measure_error(original: &RgbBuffer, quantized: &RgbBuffer) -> QuantizationError {
+    let mut squared_sum = 0.0;
+    let mut delta_e_sum = 0.0;
+    for (a, b) in original.pixels().zip(quantized.pixels()) {
+        for c in 0..3 {
+            squared_sum += (a[c] as f64 - b[c] as f64).powi(2);
+        }
+        let lab_a = Lab::from_rgb(RgbF::from_u8(*a));
+        let lab_b = Lab::from_rgb(RgbF::from_u8(*b));
+        delta_e_sum += (lab_a.distance_squared(&lab_b) as f64).sqrt();
+    }
+    let pixel_count = (original.width() as f64 * original.height() as f64).max(1.0);
+    let mse = squared_sum / (pixel_count * 3.0);
+    QuantizationError {
+        mse,
+        psnr: metrics::psnr_from_mse(mse),
+        mean_delta_e: delta_e_sum / pixel_count,
+    }
+}
+
+const SWATCH_CELL: u32 = 32;
+const SWATCH_COLUMNS: u32 = 16;
+
+pub fn render_swatch(palette: &[Rgb<u8>]) -> RgbBuffer {
+    let count = palette.len().max(1) as u32;
+    let columns = count.min(SWATCH_COLUMNS);
+    let rows = count.div_ceil(SWATCH_COLUMNS);
+    RgbBuffer::from_fn(columns * SWATCH_CELL, rows * SWATCH_CELL, |x, y| {
+        let idx = (y / SWATCH_CELL * SWATCH_COLUMNS + x / SWATCH_CELL) as usize;
+        palette.get(idx).cloned().unwrap_or(im::COLOR_WHITE)
+    })
+}
+
+type ColorCount = ([u8; 3], u32);
+
+fn color_histogram(buffer: &RgbBuffer) -> Vec<ColorCount> {
+    let mut counts: HashMap<[u8; 3], u32> = HashMap::new();
+    for pixel in buffer.pixels() {
+        *counts.entry(pixel.0).or_default() += 1;
+    }
+    let mut histogram: Vec<ColorCount> = counts.into_iter().collect();
+    // stable order keeps results deterministic across runs
+    histogram.sort_unstable();
+    histogram
+}
+
+fn weighted_mean(colors: &[ColorCount]) -> Rgb<u8> {
+    let mut sum = [0u64; 3];
+    let mut count = 0u64;
+    for (color, n) in colors {
+        for c in 0..3 {
+            sum[c] += color[c] as u64 * *n as u64;
+        }
+        count += *n as u64;
+    }
+    let count = count.max(1);
+    Rgb(sum.map(|s| ((s + count / 2) / count) as u8))
+}
+
+fn median_cut(histogram: &[ColorCount], colors: usize) -> Vec<Rgb<u8>> {
+    let mut boxes: Vec<Vec<ColorCount>> = vec![histogram.to_vec()];
+    while boxes.len() < colors {
+        // split the box with the widest channel range
+        let widest = boxes
+            .iter()
+            .enumerate()
+            .filter(|(_, b)| b.len() > 1)
+            .map(|(idx, b)| {
+                let (channel, range) = (0..3)
+                    .map(|c| {
+                        let min = b.iter().map(|(color, _)| color[c]).min().unwrap();
+                        let max = b.iter().map(|(color, _)| color[c]).max().unwrap();
+                        (c, max - min)
+                    })
+                    .max_by_key(|&(_, range)| range)
+                    .unwrap();
+                (idx, channel, range)
+            })
+            .max_by_key(|&(_, _, range)| range);
+        let Some((idx, channel, _)) = widest else {
+            break;
+        };
+
+        let mut lower = boxes.swap_remove(idx);
+        lower.sort_unstable_by_key(|(color, _)| color[channel]);
+        let total: u64 = lower.iter().map(|&(_, n)| n as u64).sum();
+        let mut acc = 0u64;
+        let mut split = lower.len() / 2;
+        for (i, &(_, n)) in lower.iter().enumerate() {
+            acc += n as u64;
+            if acc * 2 >= total {
+                split = i + 1;
+                break;
+            }
+        }
+        let split = split.clamp(1, lower.len() - 1);
+        let upper = lower.split_off(split);
+        boxes.push(lower);
+        boxes.push(upper);
+    }
+    boxes.iter().map(|b| weighted_mean(b)).collect()
+}
+
+struct OctreeNode {
+    sum: [u64; 3],
+    count: u64,
+    children: [Option<usize>; 8],
+    is_leaf: bool,
+}
+
+impl OctreeNode {
+    fn new(is_leaf: bool) -> OctreeNode {
+        OctreeNode {
+            sum: [0; 3],
+            count: 0,
+            children: [None; 8],
+            is_leaf,
+        }
+    }
+}
+
+fn octree(histogram: &[ColorCount], colors: usize) -> Vec<Rgb<u8>> {
+    const DEPTH: usize = 8;
+    let mut nodes = vec![OctreeNode::new(false)];
+    let mut reducible: Vec<Vec<usize>> = vec![Vec::new(); DEPTH];
+    reducible[0].push(0);
+    let mut leaf_count = 0;
+
+    for &(color, n) in histogram {
+        let mut node = 0;
+        for level in 0..DEPTH {
+            let shift = 7 - level;
+            let child_idx = (((color[0] >> shift) & 1) << 2
+                | ((color[1] >> shift) & 1) << 1
+                | ((color[2] >> shift) & 1)) as usize;
+            node = match nodes[node].children[child_idx] {
+                Some(child) => child,
+                None => {
+                    let is_leaf = level + 1 == DEPTH;
+                    nodes.push(OctreeNode::new(is_leaf));
+                    let child = nodes.len() - 1;
+                    if is_leaf {
+                        leaf_count += 1;
+                    } else {
+                        reducible[level + 1].push(child);
+                    }
+                    nodes[node].children[child_idx] = Some(child);
+                    child
+                }
+            };
+        }
+        let leaf = &mut nodes[node];
+        for (sum, &c) in leaf.sum.iter_mut().zip(color.iter()) {
+            *sum += c as u64 * n as u64;
+        }
+        leaf.count += n as u64;
+    }
+
+    // merge the deepest nodes into their parents until the palette fits
+    while leaf_count > colors {
+        let Some(level) = (0..DEPTH).rev().find(|&l| !reducible[l].is_empty()) else {
+            break;
+        };
+        let node = reducible[level].pop().unwrap();
+        let children: Vec<usize> = nodes[node]
+            .children
+            .iter_mut()
+            .filter_map(|child| child.take())
+            .collect();
+        for &child in children.iter() {
+            let (sum, count) = (nodes[child].sum, nodes[child].count);
+            for (total, s) in nodes[node].sum.iter_mut().zip(sum) {
+                *total += s;
+            }
+            nodes[node].count += count;
+        }
+        nodes[node].is_leaf = true;
+        leaf_count = leaf_count + 1 - children.len();
+    }
+
+    let mut palette = Vec::with_capacity(leaf_count);
+    let mut stack = vec![0];
+    while let Some(node) = stack.pop() {
+        let node = &nodes[node];
+        if node.is_leaf {
+            if node.count > 0 {
+                let count = node.count;
+                palette.push(Rgb(node.sum.map(|s| ((s + count / 2) / count) as u8)));
+            }
+        } else {
+            stack.extend(node.children.iter().flatten());
+        }
+    }
+    palette
+}
+
+// Xiaolin Wu, "Efficient Statistical Computations for Optimal Color Quantization",
+// Graphics Gems II, with 5 bit per channel moment tables
+const WU_SIDE: usize = 33;
+
+struct WuMoments {
+    weight: Vec<i64>,
+    red: Vec<i64>,
+    green: Vec<i64>,
+    blue: Vec<i64>,
+    squares: Vec<f64>,
+}
+
+#[derive(Copy, Clone, Default)]
+struct WuBox {
+    r0: usize,
+    r1: usize,
+    g0: usize,
+    g1: usize,
+    b0: usize,
+    b1: usize,
+}
+
+fn wu_index(r: usize, g: usize, b: usize) -> usize {
+    (r * WU_SIDE + g) * WU_SIDE + b
+}
+
+impl WuMoments {
+    fn new(histogram: &[ColorCount]) -> WuMoments {
+        let size = WU_SIDE * WU_SIDE * WU_SIDE;
+        let mut m = WuMoments {
+            weight: vec![0; size],
+            red: vec![0; size],
+            green: vec![0; size],
+            blue: vec![0; size],
+            squares: vec![0.0; size],
+        };
+        for &(color, n) in histogram {
+            let idx = wu_index(
+                (color[0] >> 3) as usize + 1,
+                (color[1] >> 3) as usize + 1,
+                (color[2] >> 3) as usize + 1,
+            );
+            let n = n as i64;
+            let [r, g, b] = color.map(|c| c as i64);
+            m.weight[idx] += n;
+            m.red[idx] += r * n;
+            m.green[idx] += g * n;
+            m.blue[idx] += b * n;
+            m.squares[idx] += ((r * r + g * g + b * b) * n) as f64;
+        }
+
+        // cumulative moments, each cell holds the sum over the box from the origin
+        for r in 1..WU_SIDE {
+            let mut area = [[0i64; 4]; WU_SIDE];
+            let mut area_squares = [0f64; WU_SIDE];
+            for g in 1..WU_SIDE {
+                let mut line = [0i64; 4];
+                let mut line_squares = 0.0;
+                for b in 1..WU_SIDE {
+                    let idx = wu_index(r, g, b);
+                    let prev = wu_index(r - 1, g, b);
+                    line[0] += m.weight[idx];
+                    line[1] += m.red[idx];
+                    line[2] += m.green[idx];
+                    line[3] += m.blue[idx];
+                    line_squares += m.squares[idx];
+                    for k in 0..4 {
+                        area[b][k] += line[k];
+                    }
+                    area_squares[b] += line_squares;
+                    m.weight[idx] = m.weight[prev] + area[b][0];
+                    m.red[idx] = m.red[prev] + area[b][1];
+                    m.green[idx] = m.green[prev] + area[b][2];
+                    m.blue[idx] = m.blue[prev] + area[b][3];
+                    m.squares[idx] = m.squares[prev] + area_squares[b];
+                }
+            }
+        }
+        m
+    }
+}
+
+fn wu_volume<T>(cube: &WuBox, m: &[T]) -> T
+where
+    T: Copy + std::ops::Add<Output = T> + std::ops::Sub<Output = T>,
+{
+    m[wu_index(cube.r1, cube.g1, cube.b1)]
+        - m[wu_index(cube.r1, cube.g1, cube.b0)]
+        - m[wu_index(cube.r1, cube.g0, cube.b1)]
+        + m[wu_index(cube.r1, cube.g0, cube.b0)]
+        - m[wu_index(cube.r0, cube.g1, cube.b1)]
+        + m[wu_index(cube.r0, cube.g1, cube.b0)]
+        + m[wu_index(cube.r0, cube.g0, cube.b1)]
+        - m[wu_index(cube.r0, cube.g0, cube.b0)]
+}
+
+/// volume from the table origin up to `pos` along `axis`, within the
+/// `cube` bounds on the other two axes
+fn wu_partial(cube: &WuBox, axis: usize, pos: usize, m: &[i64]) -> i64 {
+    let mut part = *cube;
+    match axis {
+        0 => {
+            part.r1 = pos;
+            part.r0 = 0;
+        }
+        1 => {
+            part.g1 = pos;
+            part.g0 = 0;
+        }
+        _ => {
+            part.b1 = pos;
+            part.b0 = 0;
+        }
+    }
+    wu_volume(&part, m)
+}
+
+fn wu_variance(cube: &WuBox, m: &WuMoments) -> f64 {
+    let r = wu_volume(cube, &m.red) as f64;
+    let g = wu_volume(cube, &m.green) as f64;
+    let b = wu_volume(cube, &m.blue) as f64;
+    let w = wu_volume(cube, &m.weight) as f64;
+    if w == 0.0 {
+        return 0.0;
+    }
+    wu_volume(cube, &m.squares) - (r * r + g * g + b * b) / w
+}
+
+/// best cut position along `axis` and the resulting between-box variance
+fn wu_maximize(cube: &WuBox, axis: usize, m: &WuMoments) -> Option<(usize, f64)> {
+    let (first, last, lower) = match axis {
+        0 => (cube.r0 + 1, cube.r1, cube.r0),
+        1 => (cube.g0 + 1, cube.g1, cube.g0),
+        _ => (cube.b0 + 1, cube.b1, cube.b0),
+    };
+    let whole = [
+        wu_volume(cube, &m.red),
+        wu_volume(cube, &m.green),
+        wu_volume(cube, &m.blue),
+        wu_volume(cube, &m.weight),
+    ];
+    let tables = [&m.red, &m.green, &m.blue, &m.weight];
+    let base: Vec<i64> = tables
+        .iter()
+        .map(|t| wu_partial(cube, axis, lower, t))
+        .collect();
+
+    let mut best: Option<(usize, f64)> = None;
+    for pos in first..last {
+        let half: Vec<i64> = tables
+            .iter()
+            .zip(base.iter())
+            .map(|(t, b)| wu_partial(cube, axis, pos, t) - b)
+            .collect();
+        let other: Vec<i64> = whole.iter().zip(half.iter()).map(|(w, h)| w - h).collect();
+        if half[3] == 0 || other[3] == 0 {
+            continue;
+        }
+        let score = |v: &[i64]| {
+            (v[0] as f64).powi(2) + (v[1] as f64).powi(2) + (v[2] as f64).powi(2)
+        } / v[3] as f64;
+        let temp = score(&half) + score(&other);
+        if best.is_none_or(|(_, max)| temp > max) {
+            best = Some((pos, temp));
+        }
+    }
+    best
+}
+
+fn wu(histogram: &[ColorCount], colors: usize) -> Vec<Rgb<u8>> {
+    let m = WuMoments::new(histogram);
+    let mut cubes = vec![WuBox {
+        r1: WU_SIDE - 1,
+        g1: WU_SIDE - 1,
+        b1: WU_SIDE - 1,
+        ..Default::default()
+    }];
+    let mut variances = vec![0.0];
+    let mut next = 0;
+
+    while cubes.len() < colors {
+        let cube = cubes[next];
+        let best = (0..3)
+            .filter_map(|axis| wu_maximize(&cube, axis, &m).map(|(pos, v)| (axis, pos, v)))
+            .max_by(|a, b| a.2.total_cmp(&b.2));
+
+        match best {
+            Some((axis, pos, _)) => {
+                let (mut lower, mut upper) = (cube, cube);
+                match axis {
+                    0 => (lower.r1, upper.r0) = (pos, pos),
+                    1 => (lower.g1, upper.g0) = (pos, pos),
+                    _ => (lower.b1, upper.b0) = (pos, pos),
+                }
+                cubes[next] = lower;
+                cubes.push(upper);
+                variances[next] = wu_variance(&lower, &m);
+                variances.push(wu_variance(&upper, &m));
+            }
+            None => variances[next] = 0.0,
+        }
+
+        let (max_idx, &max_variance) = variances
+            .iter()
+            .enumerate()
+            .max_by(|a, b| a.1.total_cmp(b.1))
+            .unwrap();
+        if max_variance <= 0.0 {
+            break;
+        }
+        next = max_idx;
+    }
+
+    cubes
+        .iter()
+        .filter_map(|cube| {
+            let w = wu_volume(cube, &m.weight);
+            (w > 0).then(|| {
+                let mean = |t: &[i64]| ((wu_volume(cube, t) + w / 2) / w) as u8;
+                Rgb([mean(&m.red), mean(&m.green), mean(&m.blue)])
+            })
+        })
+        .collect()
+}
+
+fn kmeans_lab(histogram: &[ColorCount], colors: usize) -> Vec<Rgb<u8>> {
+    const MAX_ITERATIONS: usize = 24;
+
+    let points: Vec<(Lab, f32)> = histogram
+        .iter()
+        .map(|&(color, n)| (Lab::from_rgb(RgbF::from_u8(Rgb(color))), n as f32))
+        .collect();
+    let mut centers: Vec<Lab> = median_cut(histogram, colors)
+        .into_iter()
+        .map(|c| Lab::from_rgb(RgbF::from_u8(c)))
+        .collect();
+    let mut assignment = vec![usize::MAX; points.len()];
+
+    for _ in 0..MAX_ITERATIONS {
+        let mut changed = false;
+        for (point, assigned) in points.iter().zip(assignment.iter_mut()) {
+            let nearest = centers
+                .iter()
+                .enumerate()
+                .min_by(|(_, a), (_, b)| {
+                    a.distance_squared(&point.0)
+                        .total_cmp(&b.distance_squared(&point.0))
+                })
+                .map(|(idx, _)| idx)
+                .unwrap();
+            if *assigned != nearest {
+                *assigned = nearest;
+                changed = true;
+            }
+        }
+        if !changed {
+            break;
+        }
+
+        let mut sums = vec![(0.0f32, 0.0f32, 0.0f32, 0.0f32); centers.len()];
+        for (point, &assigned) in points.iter().zip(assignment.iter()) {
+            let (lab, n) = point;
+            let sum = &mut sums[assigned];
+            sum.0 += lab.l * n;
+            sum.1 += lab.a * n;
+            sum.2 += lab.b * n;
+            sum.3 += n;
+        }
+        // empty clusters keep their previous center
+        for (center, sum) in centers.iter_mut().zip(sums.iter()) {
+            if sum.3 > 0.0 {
+                *center = Lab {
+                    l: sum.0 / sum.3,
+                    a: sum.1 / sum.3,
+                    b: sum.2 / sum.3,
+                };
+            }
+        }
+    }
+
+    centers
+        .into_iter()
+        .map(|c| c.into_rgb().round_into_u8())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::integral::tests::test_image;
+
+    #[test]
+    fn palettes_fit_the_requested_size() {
+        let buffer = test_image(64, 64);
+        for method in PaletteMethod::ALL {
+            for colors in [2, 16, 256] {
+                let quantized = quantize(&buffer, colors, method, &Dither::None);
+                let size = quantized.palette.len();
+                assert!(
+                    (1..=colors).contains(&size),
+                    "{} gave {size} of {colors} colors",
+                    method.name()
+                );
+                assert!(quantized.indices.iter().all(|&i| (i as usize) < size));
+            }
+        }
+    }
+
+    #[test]
+    fn few_colors_are_kept_exactly() {
+        let colors = [Rgb([200, 30, 30]), Rgb([20, 180, 40]), Rgb([10, 10, 240])];
+        let buffer = RgbBuffer::from_fn(9, 4, |x, y| colors[((x + y) % 3) as usize]);
+        for method in PaletteMethod::ALL {
+            let quantized = quantize(&buffer, 16, method, &Dither::None);
+            assert_eq!(quantized.palette.len(), 3, "{}", method.name());
+            assert_eq!(quantized.to_rgb(), buffer, "{}", method.name());
+        }
+    }
+}
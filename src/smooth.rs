@@ -0,0 +1,558 @@
+use crate::border::BorderMode;
+use crate::error;
+use crate::im::{self, Light};
+use crate::integral::{self, IntegralImage};
+use crate::progress;
+use crate::quantize;
+use image::Rgb;
+use std::path::PathBuf;
+
+type RgbBuffer = image::ImageBuffer<Rgb<u8>, Vec<u8>>;
+
+/// largest bilateral spatial sigma, the kernel spans 4 sigma + 1 pixels
+pub const MAX_SIGMA_SPACE: f32 = 16.0;
+/// largest non-local means patch radius
+pub const MAX_PATCH_RADIUS: u32 = 3;
+/// largest non-local means search radius, every pixel compares a patch
+/// against each pixel of the search window
+pub const MAX_SEARCH_RADIUS: u32 = 10;
+
+#[derive(Copy, Clone)]
+pub enum SmoothFilter {
+    Median,
+    Bilateral,
+    Kuwahara,
+    Guided,
+    NonLocalMeans,
+}
+
+impl SmoothFilter {
+    pub const NAMES: &'static str = "median, bilateral, kuwahara, guided, nlm, all";
+    pub const ALL: [SmoothFilter; 5] = [
+        SmoothFilter::Median,
+        SmoothFilter::Bilateral,
+        SmoothFilter::Kuwahara,
+        SmoothFilter::Guided,
+        SmoothFilter::NonLocalMeans,
+    ];
+
+    /// `all` yields every filter
+    pub fn from_name(name: &str) -> Option<Vec<SmoothFilter>> {
+        let filter = match name {
+            "median" => SmoothFilter::Median,
+            "bilateral" => SmoothFilter::Bilateral,
+            "kuwahara" => SmoothFilter::Kuwahara,
+            "guided" => SmoothFilter::Guided,
+            "nlm" => SmoothFilter::NonLocalMeans,
+            "all" => return Some(SmoothFilter::ALL.to_vec()),
+            _ => return None,
+        };
+        Some(vec![filter])
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            SmoothFilter::Median => "median",
+            SmoothFilter::Bilateral => "bilateral",
+            SmoothFilter::Kuwahara => "kuwahara",
+            SmoothFilter::Guided => "guided",
+            SmoothFilter::NonLocalMeans => "nlm",
+        }
+    }
+}
+
+pub struct SmoothOptions {
+    /// window radius of median, kuwahara and guided filters
+    pub radius: u32,
+    /// bilateral spatial sigma in pixels
+    pub sigma_space: f32,
+    /// bilateral range sigma in 0..255 intensity units
+    pub sigma_range: f32,
+    /// guided filter regularization on 0..1 intensities
+    pub eps: f32,
+    /// non-local means filtering strength in 0..255 intensity units
+    pub strength: f32,
+    pub patch_radius: u32,
+    pub search_radius: u32,
+    /// filter in linear light instead of sRGB bytes
+    pub linear: bool,
+}
+
+pub fn run(
+    batch: &im::Batch,
+    targets: &[PathBuf],
+    filters: &[SmoothFilter],
+    options: &SmoothOptions,
+    gray: im::GrayMethod,
+) -> error::Result<()> {
+    let results: Vec<String> = filters
+        .iter()
+        .flat_map(|filter| {
+            [
+                format!("{}_vs_box_mean", filter.name()),
+                filter.name().to_string(),
+            ]
+        })
+        .collect();
+    im::for_each_image(batch, targets, &results, |image, info| {
+        let buffer = image.into_rgb8();
+        let box_size = options.radius * 2 + 1;
+        let box_mean = integral::image_set_pixels_to_box_mean(
+            image::DynamicImage::ImageRgb8(buffer.clone()),
+            box_size,
+            box_size,
+            BorderMode::Shrink,
+            options.linear,
+        );
+        let original_edges = mean_gradient(&buffer, gray);
+        let report = |name: &str, smoothed: &RgbBuffer| {
+            let error = quantize::measure_error(&buffer, smoothed);
+            progress::println(format!(
+                "smoothed: `{}` {}, psnr {:.2} dB, edge retention {:.1}%",
+                info.name(),
+                name,
+                error.psnr,
+                100.0 * mean_gradient(smoothed, gray) / original_edges.max(f64::EPSILON)
+            ));
+        };
+        report(&format!("box_mean_{box_size}x{box_size}"), &box_mean);
+
+        for &filter in filters {
+            let smoothed = smooth(&buffer, filter, options);
+            report(filter.name(), &smoothed);
+            im::image_buffer_save(
+                side_by_side(&box_mean, &smoothed),
+                &info.save_path_concat(&format!("{}_vs_box_mean", filter.name())),
+            )?;
+            im::image_buffer_save(smoothed, &info.save_path_concat(filter.name()))?;
+        }
+        Ok(())
+    })
+}
+
+pub fn smooth(buffer: &RgbBuffer, filter: SmoothFilter, options: &SmoothOptions) -> RgbBuffer {
+    match filter {
+        // order statistics commute with the monotonic sRGB transfer, the
+        // median of linear light is the same pixel
+        SmoothFilter::Median => median(buffer, options.radius),
+        _ if options.linear => im::buffer_from_linear(&smooth_light(
+            &im::buffer_into_linear(buffer),
+            filter,
+            options,
+        )),
+        _ => smooth_light(buffer, filter, options),
+    }
+}
+
+/// `filter` on sRGB bytes or 16 bit linear light
+fn smooth_light<P: Light>(
+    buffer: &image::ImageBuffer<Rgb<P>, Vec<P>>,
+    filter: SmoothFilter,
+    options: &SmoothOptions,
+) -> image::ImageBuffer<Rgb<P>, Vec<P>>
+where
+    Rgb<P>: image::Pixel<Subpixel = P>,
+{
+    match filter {
+        SmoothFilter::Median => median(buffer, options.radius),
+        SmoothFilter::Bilateral => bilateral(buffer, options.sigma_space, options.sigma_range),
+        SmoothFilter::Kuwahara => kuwahara(buffer, options.radius),
+        SmoothFilter::Guided => guided(buffer, options.radius, options.eps),
+        SmoothFilter::NonLocalMeans => non_local_means(
+            buffer,
+            options.patch_radius,
+            options.search_radius,
+            options.strength,
+        ),
+    }
+}
+
+/// mean sobel magnitude of the gray intensity, sharper images score higher
+fn mean_gradient(buffer: &RgbBuffer, gray: im::GrayMethod) -> f64 {
+    let gray = image::GrayImage::from_fn(buffer.width(), buffer.height(), |x, y| {
+        image::Luma([gray.gray_u8(*buffer.get_pixel(x, y))])
+    });
+    let gradients = imageproc::gradients::sobel_gradients(&gray);
+    let sum: f64 = gradients.pixels().map(|p| p[0] as f64).sum();
+    sum / (gradients.width() as f64 * gradients.height() as f64).max(1.0)
+}
+
+const COMPARISON_GAP: u32 = 4;
+
+/// `left` and `right` next to each other with a white gap
+fn side_by_side(left: &RgbBuffer, right: &RgbBuffer) -> RgbBuffer {
+    let (w, h) = left.dimensions();
+    RgbBuffer::from_fn(w * 2 + COMPARISON_GAP, h, |x, y| {
+        if x < w {
+            *left.get_pixel(x, y)
+        } else if x < w + COMPARISON_GAP {
+            im::COLOR_WHITE
+        } else {
+            *right.get_pixel(x - w - COMPARISON_GAP, y)
+        }
+    })
+}
+
+/// Huang's sliding histogram median over a `2r+1` square window, each step
+/// right swaps one window column, windows shrink at the image borders
+pub fn median<P: Light>(
+    buffer: &image::ImageBuffer<Rgb<P>, Vec<P>>,
+    radius: u32,
+) -> image::ImageBuffer<Rgb<P>, Vec<P>>
+where
+    Rgb<P>: image::Pixel<Subpixel = P>,
+{
+    let (width, height) = buffer.dimensions();
+    let mut output = image::ImageBuffer::new(width, height);
+    for y in 0..height {
+        let y0 = y.saturating_sub(radius);
+        let y1 = (y + radius + 1).min(height);
+        let mut channels: [RunningMedian; 3] = std::array::from_fn(|_| RunningMedian::new::<P>());
+        let mut count = 0u32;
+        let add_column = |channels: &mut [RunningMedian; 3], x: u32, sign: i32| {
+            for ys in y0..y1 {
+                let pixel = buffer.get_pixel(x, ys);
+                for c in 0..3 {
+                    channels[c].add(pixel[c].into() as usize, sign);
+                }
+            }
+        };
+        for x in 0..(radius + 1).min(width) {
+            add_column(&mut channels, x, 1);
+            count += y1 - y0;
+        }
+
+        for x in 0..width {
+            if x > 0 {
+                if x > radius {
+                    add_column(&mut channels, x - radius - 1, -1);
+                    count -= y1 - y0;
+                }
+                if x + radius < width {
+                    add_column(&mut channels, x + radius, 1);
+                    count += y1 - y0;
+                }
+            }
+            let half = count.div_ceil(2);
+            let pixel = [0, 1, 2].map(|c| {
+                P::from(channels[c].seek(half)).expect("histogram bins are channel values")
+            });
+            output.put_pixel(x, y, Rgb(pixel));
+        }
+    }
+    output
+}
+
+/// window histogram with its last median and the count of values below it,
+/// the median moves from there instead of being searched from the first bin
+struct RunningMedian {
+    histogram: Vec<u32>,
+    median: usize,
+    below: u32,
+}
+
+impl RunningMedian {
+    /// one bin per value of the channel type
+    fn new<P: Light>() -> RunningMedian {
+        RunningMedian {
+            histogram: vec![0; P::DEFAULT_MAX_VALUE.into() as usize + 1],
+            median: 0,
+            below: 0,
+        }
+    }
+
+    fn add(&mut self, value: usize, sign: i32) {
+        self.histogram[value] = self.histogram[value].wrapping_add_signed(sign);
+        if value < self.median {
+            self.below = self.below.wrapping_add_signed(sign);
+        }
+    }
+
+    /// smallest value with at least `half` window values at or below it
+    fn seek(&mut self, half: u32) -> usize {
+        while self.below >= half {
+            self.median -= 1;
+            self.below -= self.histogram[self.median];
+        }
+        while self.below + self.histogram[self.median] < half {
+            self.below += self.histogram[self.median];
+            self.median += 1;
+        }
+        self.median
+    }
+}
+
+/// weights neighbours by spatial distance and rgb difference, the window
+/// covers two spatial sigmas
+pub fn bilateral<P: Light>(
+    buffer: &image::ImageBuffer<Rgb<P>, Vec<P>>,
+    sigma_space: f32,
+    sigma_range: f32,
+) -> image::ImageBuffer<Rgb<P>, Vec<P>>
+where
+    Rgb<P>: image::Pixel<Subpixel = P>,
+{
+    let (width, height) = buffer.dimensions();
+    let radius = (sigma_space * 2.0).ceil().max(1.0) as i64;
+    let size = (radius * 2 + 1) as usize;
+    let spatial: Vec<f32> = (0..size * size)
+        .map(|idx| {
+            let dx = (idx % size) as f32 - radius as f32;
+            let dy = (idx / size) as f32 - radius as f32;
+            (-(dx * dx + dy * dy) / (2.0 * sigma_space * sigma_space)).exp()
+        })
+        .collect();
+    // range weight by squared rgb distance in 0..255 units
+    let range = |d2: f32| (-d2 / (2.0 * sigma_range * sigma_range)).exp();
+
+    image::ImageBuffer::from_fn(width, height, |x, y| {
+        let center = buffer.get_pixel(x, y).0.map(P::into_byte_units);
+        let mut acc = [0.0f32; 3];
+        let mut total = 0.0f32;
+        for dy in -radius..=radius {
+            let ys = y as i64 + dy;
+            if ys < 0 || ys >= height as i64 {
+                continue;
+            }
+            for dx in -radius..=radius {
+                let xs = x as i64 + dx;
+                if xs < 0 || xs >= width as i64 {
+                    continue;
+                }
+                let pixel = buffer
+                    .get_pixel(xs as u32, ys as u32)
+                    .0
+                    .map(P::into_byte_units);
+                let d2: f32 = (0..3).map(|c| (pixel[c] - center[c]).powi(2)).sum();
+                let weight =
+                    spatial[((dy + radius) as usize) * size + (dx + radius) as usize] * range(d2);
+                total += weight;
+                for c in 0..3 {
+                    acc[c] += pixel[c] * weight;
+                }
+            }
+        }
+        Rgb(acc.map(|v| P::from_byte_units((v / total) as f64)))
+    })
+}
+
+/// mean of the least varying of the four `r+1` square quadrants around each
+/// pixel, variance is summed over channels
+pub fn kuwahara<P: Light>(
+    buffer: &image::ImageBuffer<Rgb<P>, Vec<P>>,
+    radius: u32,
+) -> image::ImageBuffer<Rgb<P>, Vec<P>>
+where
+    Rgb<P>: image::Pixel<Subpixel = P>,
+{
+    let (width, height) = buffer.dimensions();
+    let sums = IntegralImage::new(buffer);
+    let squares = IntegralImage::new_squared(buffer);
+    image::ImageBuffer::from_fn(width, height, |x, y| {
+        let (xl, xr) = (x.saturating_sub(radius), (x + radius + 1).min(width));
+        let (yt, yb) = (y.saturating_sub(radius), (y + radius + 1).min(height));
+        let quadrants = [
+            (xl, yt, x + 1, y + 1),
+            (x, yt, xr, y + 1),
+            (xl, y, x + 1, yb),
+            (x, y, xr, yb),
+        ];
+        let (x0, y0, x1, y1) = quadrants
+            .into_iter()
+            .map(|(x0, y0, x1, y1)| {
+                let area = ((x1 - x0) * (y1 - y0)) as f64;
+                let sum = sums.sum(x0, y0, x1, y1);
+                let square = squares.sum(x0, y0, x1, y1);
+                let variance: f64 = (0..3)
+                    .map(|c| square[c] as f64 / area - (sum[c] as f64 / area).powi(2))
+                    .sum();
+                ((x0, y0, x1, y1), variance)
+            })
+            .min_by(|(_, a), (_, b)| a.total_cmp(b))
+            .map(|(quadrant, _)| quadrant)
+            .expect("four quadrants");
+        sums.mean(x0, y0, x1, y1)
+    })
+}
+
+/// self guided filter of He et al. per channel, a local linear model of the
+/// image is fitted in every `2r+1` window and averaged
+pub fn guided<P: Light>(
+    buffer: &image::ImageBuffer<Rgb<P>, Vec<P>>,
+    radius: u32,
+    eps: f32,
+) -> image::ImageBuffer<Rgb<P>, Vec<P>>
+where
+    Rgb<P>: image::Pixel<Subpixel = P>,
+{
+    let (width, height) = buffer.dimensions();
+    let (w, h) = (width as usize, height as usize);
+    let mut output = buffer.clone();
+    for c in 0..3 {
+        let plane: Vec<f64> = buffer
+            .pixels()
+            .map(|p| p[c].into_byte_units() as f64 / 255.0)
+            .collect();
+        let squared: Vec<f64> = plane.iter().map(|v| v * v).collect();
+        let mean = box_mean(&plane, w, h, radius);
+        let mean_squared = box_mean(&squared, w, h, radius);
+
+        let mut a = vec![0.0; w * h];
+        let mut b = vec![0.0; w * h];
+        for idx in 0..w * h {
+            let variance = mean_squared[idx] - mean[idx] * mean[idx];
+            a[idx] = variance / (variance + eps as f64);
+            b[idx] = mean[idx] - a[idx] * mean[idx];
+        }
+        let mean_a = box_mean(&a, w, h, radius);
+        let mean_b = box_mean(&b, w, h, radius);
+        for (idx, pixel) in output.pixels_mut().enumerate() {
+            let value = mean_a[idx] * plane[idx] + mean_b[idx];
+            pixel[c] = P::from_byte_units(value * 255.0);
+        }
+    }
+    output
+}
+
+/// mean over a `2r+1` square window shrinking at the borders
+fn box_mean(plane: &[f64], width: usize, height: usize, radius: u32) -> Vec<f64> {
+    let radius = radius as usize;
+    let stride = width + 1;
+    let mut sums = vec![0.0f64; stride * (height + 1)];
+    for y in 0..height {
+        let mut row_sum = 0.0;
+        for x in 0..width {
+            row_sum += plane[y * width + x];
+            sums[(y + 1) * stride + x + 1] = sums[y * stride + x + 1] + row_sum;
+        }
+    }
+    let mut output = vec![0.0; width * height];
+    for y in 0..height {
+        let (y0, y1) = (y.saturating_sub(radius), (y + radius + 1).min(height));
+        for x in 0..width {
+            let (x0, x1) = (x.saturating_sub(radius), (x + radius + 1).min(width));
+            let sum = sums[y1 * stride + x1] + sums[y0 * stride + x0]
+                - sums[y0 * stride + x1]
+                - sums[y1 * stride + x0];
+            output[y * width + x] = sum / ((x1 - x0) * (y1 - y0)) as f64;
+        }
+    }
+    output
+}
+
+/// Buades non-local means, every pixel in the search window is weighted by
+/// the similarity of its `2p+1` patch, patches clamp at the borders
+pub fn non_local_means<P: Light>(
+    buffer: &image::ImageBuffer<Rgb<P>, Vec<P>>,
+    patch_radius: u32,
+    search_radius: u32,
+    strength: f32,
+) -> image::ImageBuffer<Rgb<P>, Vec<P>>
+where
+    Rgb<P>: image::Pixel<Subpixel = P>,
+{
+    let (width, height) = buffer.dimensions();
+    let (p, s) = (patch_radius as i64, search_radius as i64);
+    let pixel = |x: i64, y: i64| {
+        let x = x.clamp(0, width as i64 - 1) as u32;
+        let y = y.clamp(0, height as i64 - 1) as u32;
+        buffer.get_pixel(x, y).0.map(P::into_byte_units)
+    };
+    let patch_samples = ((2 * p + 1) * (2 * p + 1) * 3) as f32;
+    let h2 = strength * strength;
+
+    image::ImageBuffer::from_fn(width, height, |x, y| {
+        let (x, y) = (x as i64, y as i64);
+        let mut acc = [0.0f32; 3];
+        let mut total = 0.0f32;
+        let mut max_weight = 0.0f32;
+        for qy in (y - s).max(0)..=(y + s).min(height as i64 - 1) {
+            for qx in (x - s).max(0)..=(x + s).min(width as i64 - 1) {
+                if qx == x && qy == y {
+                    continue;
+                }
+                let mut distance = 0.0f32;
+                for dy in -p..=p {
+                    for dx in -p..=p {
+                        let a = pixel(x + dx, y + dy);
+                        let b = pixel(qx + dx, qy + dy);
+                        for c in 0..3 {
+                            distance += (a[c] - b[c]).powi(2);
+                        }
+                    }
+                }
+                let weight = (-(distance / patch_samples) / h2).exp();
+                max_weight = max_weight.max(weight);
+                total += weight;
+                let q = pixel(qx, qy);
+                for c in 0..3 {
+                    acc[c] += q[c] * weight;
+                }
+            }
+        }
+        // the pixel itself counts as much as its most similar neighbour
+        let center = pixel(x, y);
+        let self_weight = if total > 0.0 { max_weight } else { 1.0 };
+        total += self_weight;
+        for c in 0..3 {
+            acc[c] += center[c] * self_weight;
+        }
+        Rgb(acc.map(|v| P::from_byte_units((v / total) as f64)))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::integral::tests::{test_image, IMAGE_SIZES};
+
+    /// lower median of the border clipped window, sorted per channel
+    fn brute_force_median<P: Light>(
+        buffer: &image::ImageBuffer<Rgb<P>, Vec<P>>,
+        radius: u32,
+    ) -> image::ImageBuffer<Rgb<P>, Vec<P>>
+    where
+        Rgb<P>: image::Pixel<Subpixel = P>,
+    {
+        let (width, height) = buffer.dimensions();
+        image::ImageBuffer::from_fn(width, height, |x, y| {
+            let mut window: [Vec<P>; 3] = Default::default();
+            for ys in y.saturating_sub(radius)..(y + radius + 1).min(height) {
+                for xs in x.saturating_sub(radius)..(x + radius + 1).min(width) {
+                    for (c, values) in window.iter_mut().enumerate() {
+                        values.push(buffer.get_pixel(xs, ys)[c]);
+                    }
+                }
+            }
+            Rgb(window.map(|mut values| {
+                values.sort_by(|a, b| a.partial_cmp(b).expect("integer channels"));
+                values[(values.len() - 1) / 2]
+            }))
+        })
+    }
+
+    #[test]
+    fn median_matches_brute_force() {
+        for (width, height) in IMAGE_SIZES {
+            let buffer = test_image(width, height);
+            let wide = image::ImageBuffer::<Rgb<u16>, Vec<u16>>::from_fn(width, height, |x, y| {
+                Rgb(buffer
+                    .get_pixel(x, y)
+                    .0
+                    .map(|v| v as u16 * 256 + (x * y) as u16 % 7))
+            });
+            for radius in [0, 1, 2, 5] {
+                assert_eq!(
+                    median(&buffer, radius),
+                    brute_force_median(&buffer, radius),
+                    "{width}x{height} radius {radius}"
+                );
+                assert_eq!(
+                    median(&wide, radius),
+                    brute_force_median(&wide, radius),
+                    "16 bit {width}x{height} radius {radius}"
+                );
+            }
+        }
+    }
+}
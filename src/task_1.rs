@@ -1,27 +1,185 @@
-use super::im::{self, RgbF};
+use super::background;
+use super::draw;
+use super::error;
+use super::evaluate;
+use super::im::{self, Rect, RgbF};
+use super::progress;
 use std::path::PathBuf;
 
-pub fn run(target: &PathBuf) {
-    for (image, info) in im::open_and_setup_output(target) {
-        analyze_image(image, info);
+type RgbBuffer = image::ImageBuffer<image::Rgb<u8>, Vec<u8>>;
+
+pub struct Options {
+    pub gray: im::GrayMethod,
+    pub overlay: BoundsOverlay,
+    pub evaluation: Option<evaluate::Evaluation>,
+    pub crop: Option<CropOptions>,
+    /// estimate the background in linear light
+    pub linear: bool,
+}
+
+#[derive(Copy, Clone)]
+pub struct CropOptions {
+    /// pixels added around each bound, clamped to the image
+    pub padding: u32,
+    /// pads the shorter side with black to make square crops
+    pub square: bool,
+    /// bounds with width or height below this are skipped
+    pub min_size: u32,
+}
+
+/// how bounds are drawn, without the box style
+#[derive(Copy, Clone)]
+pub enum OverlayMode {
+    Boxes,
+    Lines,
+}
+
+impl OverlayMode {
+    pub const NAMES: &'static str = "boxes, lines";
+
+    pub fn from_name(name: &str) -> Option<OverlayMode> {
+        match name {
+            "boxes" => Some(OverlayMode::Boxes),
+            "lines" => Some(OverlayMode::Lines),
+            _ => None,
+        }
     }
 }
 
-fn analyze_image(image: image::DynamicImage, info: im::ImageInfo) {
-    image_into_black_white(
-        image.clone(),
-        &info.save_path_concat("black_white", image::ImageFormat::Png),
-    );
+#[derive(Copy, Clone)]
+pub enum BoundsOverlay {
+    /// full width rows and full height columns at each minima
+    Lines,
+    /// outlined rectangles between minima pairs with index labels
+    Boxes {
+        thickness: u32,
+        color: image::Rgb<u8>,
+    },
+}
+
+/// returns false when evaluation against ground truth did not pass, the
+/// scorecard of the images that succeeded is printed even if others failed
+pub fn run(batch: &im::Batch, targets: &[PathBuf], options: Options) -> error::Result<bool> {
+    let scores = std::sync::Mutex::new(Vec::new());
+    // the bounds feed the scorecard and the crop count is only known after
+    // analysis, so images are always processed
+    let processed = im::for_each_image(batch, targets, &[], |image, info| {
+        let relative = info.relative_path().to_path_buf();
+        let name = relative.to_string_lossy().to_string();
+        let bounds = analyze_image(image, info, &options)?;
+
+        if let Some(evaluation) = &options.evaluation {
+            match evaluation.annotations.get(&relative) {
+                Some(truth) => scores
+                    .lock()
+                    .expect("scores lock")
+                    .push(evaluate::score_image(&name, truth, &bounds)),
+                None => progress::eprintln(format!(
+                    "no annotations found for `{}`, skipped",
+                    relative.to_string_lossy()
+                )),
+            }
+        }
+        Ok(())
+    });
+
+    // images finish in any order across workers
+    let mut scores = scores.into_inner().expect("scores lock");
+    scores.sort_by(|a, b| a.name.cmp(&b.name));
+    let passed = match options.evaluation {
+        Some(evaluation) => {
+            evaluate::print_scorecard(&scores, &evaluation.iou_thresholds, evaluation.min_f1)
+        }
+        None => true,
+    };
+    processed.map(|()| passed)
+}
+
+fn analyze_image(
+    image: image::DynamicImage,
+    info: im::ImageInfo,
+    options: &Options,
+) -> error::Result<Vec<Rect>> {
+    // flat-field corrected copy for uneven or textured backgrounds
+    let buffer = image.to_rgb8();
+    let background = background::estimate_background(&buffer, options.linear);
+    let flattened = background::flatten_background(&buffer, &background);
+    im::image_buffer_save(background, &info.save_path_concat("background"))?;
+    im::image_buffer_save(flattened.clone(), &info.save_path_concat("flattened"))?;
+
+    let bounds = analyze_variant(image, &info, options, "")?;
+    analyze_variant(
+        image::DynamicImage::ImageRgb8(flattened),
+        &info,
+        options,
+        "flattened_",
+    )?;
+
+    if let Some(crop) = options.crop {
+        export_crops(&buffer, &bounds, crop, &info)?;
+    }
+    Ok(bounds)
+}
+
+fn export_crops(
+    buffer: &image::ImageBuffer<image::Rgb<u8>, Vec<u8>>,
+    bounds: &[Rect],
+    crop: CropOptions,
+    info: &im::ImageInfo,
+) -> error::Result<()> {
+    let (w, h) = buffer.dimensions();
+    for (idx, rect) in bounds.iter().enumerate() {
+        if rect.width < crop.min_size || rect.height < crop.min_size {
+            continue;
+        }
+        let x0 = rect.x.saturating_sub(crop.padding);
+        let y0 = rect.y.saturating_sub(crop.padding);
+        let x1 = (rect.x + rect.width + crop.padding).min(w);
+        let y1 = (rect.y + rect.height + crop.padding).min(h);
+        let cropped = image::imageops::crop_imm(buffer, x0, y0, x1 - x0, y1 - y0).to_image();
+
+        let cropped = if crop.square {
+            let side = cropped.width().max(cropped.height());
+            let mut square = image::ImageBuffer::from_pixel(side, side, im::COLOR_BLACK);
+            image::imageops::replace(
+                &mut square,
+                &cropped,
+                ((side - cropped.width()) / 2) as i64,
+                ((side - cropped.height()) / 2) as i64,
+            );
+            square
+        } else {
+            cropped
+        };
+
+        im::image_buffer_save(cropped, &info.save_path_concat(&format!("crop_{idx}")))?;
+    }
+    Ok(())
+}
+
+fn analyze_variant(
+    image: image::DynamicImage,
+    info: &im::ImageInfo,
+    options: &Options,
+    prefix: &str,
+) -> error::Result<Vec<Rect>> {
+    let path = |postfix: &str| info.save_path_concat(&format!("{prefix}{postfix}"));
+    image_into_black_white(image.clone(), &path("black_white"))?;
     image_into_grayscale(
         image,
-        &info.save_path_concat("grayscale", image::ImageFormat::Png),
-        &info.save_path_concat("horizontal", image::ImageFormat::Png),
-        &info.save_path_concat("vertical", image::ImageFormat::Png),
-        &info.save_path_concat("bounds", image::ImageFormat::Png),
-    );
+        options.gray,
+        options.overlay,
+        &path("grayscale"),
+        &path("horizontal"),
+        &path("vertical"),
+        &path("bounds"),
+    )
 }
 
-fn image_into_black_white(image: image::DynamicImage, save_path: &PathBuf) {
+fn image_into_black_white(
+    image: image::DynamicImage,
+    save_path: &im::SavePath,
+) -> error::Result<()> {
     let mut buffer = image.into_rgb8();
 
     for (_, _, pixel) in buffer.enumerate_pixels_mut() {
@@ -34,48 +192,104 @@ fn image_into_black_white(image: image::DynamicImage, save_path: &PathBuf) {
         };
     }
 
-    im::image_buffer_save_png(buffer, save_path);
+    im::image_buffer_save(buffer, save_path)
 }
 
-fn image_into_grayscale(
-    image: image::DynamicImage,
-    g_path: &PathBuf,
-    h_path: &PathBuf,
-    v_path: &PathBuf,
-    m_path: &PathBuf,
-) {
-    let mut buffer = image.into_rgb8();
+/// white where the `gray` intensity reaches `level`, black elsewhere
+pub fn threshold(buffer: &RgbBuffer, gray: im::GrayMethod, level: f32) -> image::GrayImage {
+    image::GrayImage::from_fn(buffer.width(), buffer.height(), |x, y| {
+        let intensity = gray.intensity(RgbF::from_u8(*buffer.get_pixel(x, y)));
+        image::Luma([if intensity >= level { 255 } else { 0 }])
+    })
+}
 
-    for (_, _, pixel) in buffer.enumerate_pixels_mut() {
-        // grayscale weights taken from: https://en.wikipedia.org/wiki/Grayscale
-        let color = RgbF::from_u8(*pixel);
-        let scale = color.r * 0.299 + color.g * 0.587 + color.b * 0.114;
+/// copy with the `gray` intensity in every channel
+pub fn grayscale(buffer: &RgbBuffer, gray: im::GrayMethod) -> RgbBuffer {
+    let mut buffer = buffer.clone();
+    for pixel in buffer.pixels_mut() {
+        let scale = gray.intensity(RgbF::from_u8(*pixel));
         *pixel = RgbF::new(scale, scale, scale).into_u8();
     }
+    buffer
+}
+
+/// row and column profiles of a gray image and the cells between their
+/// deepest minimas
+pub struct Projections {
+    /// gray sum of each row, averaged over groups of `smoothing` rows
+    pub rows: Vec<f32>,
+    /// gray sum of each column, averaged over groups of `smoothing` columns
+    pub columns: Vec<f32>,
+    /// indices into `rows` of the deepest local minimas
+    pub row_minimas: Vec<usize>,
+    /// indices into `columns` of the deepest local minimas
+    pub column_minimas: Vec<usize>,
+    /// image rows or columns behind each profile entry
+    pub smoothing: usize,
+    /// cells between neighbouring minimas and image edges, in row major order
+    pub bounds: Vec<Rect>,
+}
 
+/// profiles of a `grayscale` image, intensities are read from the red channel
+pub fn project(gray: &RgbBuffer, smoothing: usize, minima_count: usize) -> Projections {
     // accumulate h and v projections from grayscale values
-    let mut horizontal = Vec::new();
-    let mut vertical = Vec::new();
-    horizontal.resize_with(buffer.height() as usize, || 0.0);
-    vertical.resize_with(buffer.width() as usize, || 0.0);
-    for (x, y, pixel) in buffer.enumerate_pixels_mut() {
+    let mut horizontal = vec![0.0; gray.height() as usize];
+    let mut vertical = vec![0.0; gray.width() as usize];
+    for (x, y, pixel) in gray.enumerate_pixels() {
         let scale = RgbF::from_u8(*pixel).r;
         horizontal[y as usize] += scale;
         vertical[x as usize] += scale;
     }
 
     // smooth and compute local minimas
-    const SMOOTH_FACTOR: usize = 4;
-    let h_reduced: Vec<f32> = horizontal
-        .chunks(SMOOTH_FACTOR)
-        .map(|chunk| chunk.iter().sum::<f32>() / (chunk.len() as f32))
-        .collect();
-    let v_reduced: Vec<f32> = vertical
-        .chunks(SMOOTH_FACTOR)
-        .map(|chunk| chunk.iter().sum::<f32>() / (chunk.len() as f32))
-        .collect();
-    let v_minimas = find_local_minimas(&v_reduced, 3);
-    let h_minimas = find_local_minimas(&h_reduced, 3);
+    let reduce = |profile: Vec<f32>| -> Vec<f32> {
+        profile
+            .chunks(smoothing)
+            .map(|chunk| chunk.iter().sum::<f32>() / (chunk.len() as f32))
+            .collect()
+    };
+    let rows = reduce(horizontal);
+    let columns = reduce(vertical);
+    let row_minimas = find_local_minimas(&rows, minima_count);
+    let column_minimas = find_local_minimas(&columns, minima_count);
+    let bounds = minimas_into_bounds(
+        &column_minimas,
+        &row_minimas,
+        smoothing,
+        gray.width(),
+        gray.height(),
+    );
+    Projections {
+        rows,
+        columns,
+        row_minimas,
+        column_minimas,
+        smoothing,
+        bounds,
+    }
+}
+
+const SMOOTH_FACTOR: usize = 4;
+const MINIMA_COUNT: usize = 3;
+
+fn image_into_grayscale(
+    image: image::DynamicImage,
+    gray: im::GrayMethod,
+    overlay: BoundsOverlay,
+    g_path: &im::SavePath,
+    h_path: &im::SavePath,
+    v_path: &im::SavePath,
+    m_path: &im::SavePath,
+) -> error::Result<Vec<Rect>> {
+    let buffer = grayscale(&image.into_rgb8(), gray);
+    let Projections {
+        rows: h_reduced,
+        columns: v_reduced,
+        row_minimas: h_minimas,
+        column_minimas: v_minimas,
+        bounds,
+        ..
+    } = project(&buffer, SMOOTH_FACTOR, MINIMA_COUNT);
 
     // render h graph and minimas
     let mut h_buffer = buffer.clone();
@@ -111,26 +325,78 @@ fn image_into_grayscale(
 
     // render bounds and minimas
     let mut m_buffer = buffer.clone();
-    for (x, y, pixel) in m_buffer.enumerate_pixels_mut() {
-        let h_idx = y as usize / SMOOTH_FACTOR;
-        if h_minimas.iter().cloned().find(|&i| i == h_idx).is_some() {
-            *pixel = im::COLOR_GREEN;
-            continue;
+    match overlay {
+        BoundsOverlay::Lines => {
+            for (x, y, pixel) in m_buffer.enumerate_pixels_mut() {
+                let h_idx = y as usize / SMOOTH_FACTOR;
+                if h_minimas.iter().cloned().find(|&i| i == h_idx).is_some() {
+                    *pixel = im::COLOR_GREEN;
+                    continue;
+                }
+                let v_idx = x as usize / SMOOTH_FACTOR;
+                if v_minimas.iter().cloned().find(|&i| i == v_idx).is_some() {
+                    *pixel = im::COLOR_GREEN;
+                    continue;
+                }
+            }
         }
-        let v_idx = x as usize / SMOOTH_FACTOR;
-        if v_minimas.iter().cloned().find(|&i| i == v_idx).is_some() {
-            *pixel = im::COLOR_GREEN;
-            continue;
+        BoundsOverlay::Boxes { thickness, color } => {
+            let label_scale = thickness.max(2);
+            for (idx, rect) in bounds.iter().enumerate() {
+                draw::draw_rect_outline(&mut m_buffer, *rect, thickness, color);
+                draw::draw_label(
+                    &mut m_buffer,
+                    *rect,
+                    idx,
+                    label_scale,
+                    color,
+                    im::COLOR_WHITE,
+                );
+            }
         }
     }
 
-    im::image_buffer_save_png(buffer, g_path);
-    im::image_buffer_save_png(h_buffer, h_path);
-    im::image_buffer_save_png(v_buffer, v_path);
-    im::image_buffer_save_png(m_buffer, m_path);
+    im::image_buffer_save(buffer, g_path)?;
+    im::image_buffer_save(h_buffer, h_path)?;
+    im::image_buffer_save(v_buffer, v_path)?;
+    im::image_buffer_save(m_buffer, m_path)?;
+    Ok(bounds)
+}
+
+/// cells between neighbouring boundaries, the minimas and the image edges
+fn minimas_into_bounds(
+    v_minimas: &[usize],
+    h_minimas: &[usize],
+    smooth_factor: usize,
+    width: u32,
+    height: u32,
+) -> Vec<Rect> {
+    let boundaries = |minimas: &[usize], len: u32| -> Vec<u32> {
+        let mut positions: Vec<u32> = minimas
+            .iter()
+            .map(|&idx| ((idx * smooth_factor + smooth_factor / 2) as u32).min(len))
+            .chain([0, len])
+            .collect();
+        positions.sort();
+        positions.dedup();
+        positions
+    };
+    let xs = boundaries(v_minimas, width);
+    let ys = boundaries(h_minimas, height);
+
+    let mut bounds = Vec::new();
+    for y in ys.windows(2) {
+        for x in xs.windows(2) {
+            bounds.push(Rect::new(x[0], y[0], x[1] - x[0], y[1] - y[0]));
+        }
+    }
+    bounds
 }
 
 fn find_local_minimas(data: &[f32], minima_count: usize) -> Vec<usize> {
+    if data.len() < 3 {
+        return Vec::new();
+    }
     let mut minima_scores = Vec::<f32>::new();
 
     // total left + right growth minima scoring
@@ -173,7 +439,7 @@ fn find_local_minimas(data: &[f32], minima_count: usize) -> Vec<usize> {
         if let Some((index, _)) = minima_scores
             .iter()
             .enumerate()
-            .max_by(|(_, &a), (_, &b)| a.partial_cmp(&b).unwrap())
+            .max_by(|(_, &a), (_, &b)| a.total_cmp(&b))
         {
             minima_indices.push(index + 1);
             minima_scores[index] = f32::MIN;
@@ -184,3 +450,20 @@ fn find_local_minimas(data: &[f32], minima_count: usize) -> Vec<usize> {
 
     minima_indices
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bounds_reach_the_image_edges() {
+        let bounds = minimas_into_bounds(&[2, 5], &[], 2, 16, 4);
+        let xs: Vec<(u32, u32)> = bounds.iter().map(|b| (b.x, b.width)).collect();
+        assert_eq!(xs, vec![(0, 5), (5, 6), (11, 5)]);
+        assert!(bounds.iter().all(|b| (b.y, b.height) == (0, 4)));
+
+        // a minima on the edge does not add an empty cell
+        let bounds = minimas_into_bounds(&[0], &[], 1, 4, 4);
+        assert_eq!(bounds, vec![Rect::new(0, 0, 4, 4)]);
+    }
+}
@@ -1,116 +1,154 @@
+use crate::error::{self, Error};
 use crate::im;
-use image::{GenericImage, GenericImageView, Pixel};
+use image::{GenericImage, GenericImageView};
 use std::path::PathBuf;
 
-pub fn run(target: &PathBuf, width_downsize: u32) {
-    for (image, info) in im::open_and_setup_output(target) {
-        resize_image(image, info, width_downsize)
+pub fn run(batch: &im::Batch, targets: &[PathBuf], width_downsize: u32) -> error::Result<()> {
+    let mut results = vec!["gradient", "resized"];
+    if width_downsize > 0 {
+        results.extend(["removed_path", "dp_table_weights"]);
     }
+    let results: Vec<String> = results.into_iter().map(String::from).collect();
+    im::for_each_image(batch, targets, &results, |image, info| {
+        resize_image(image, info, width_downsize)
+    })
 }
 
-fn resize_image(mut image: image::DynamicImage, info: im::ImageInfo, mut width_downsize: u32) {
+fn resize_image(
+    image: image::DynamicImage,
+    info: im::ImageInfo,
+    mut width_downsize: u32,
+) -> error::Result<()> {
     // limiting downsize amount
     if image.width() <= width_downsize {
-        width_downsize = image.width() - 1;
+        width_downsize = image.width().saturating_sub(1);
     }
 
     // visualize scaled up gradient image
-    let gradient = gradient_magnitude(&image, 100);
-    im::image_buffer_luma16_save_png(
-        gradient,
-        &info.save_path_concat("gradient", image::ImageFormat::Png),
-    );
+    let gradient = visualize_energy(&gradient_magnitude(&image), 100);
+    im::image_buffer_luma16_save(gradient, &info.save_path_concat("gradient"))?;
 
-    let mut visualize = true;
-    for _ in 0..width_downsize {
-        let grad = gradient_magnitude(&image, 1);
-        let table = DPTable::from_gradient_buffer(&grad);
-        let path = Path::from_dp_table(&table);
+    let carved = carve(image, width_downsize, true)?;
+    if let Some((removed_path, weights)) = carved.first_seam {
+        im::image_buffer_save(removed_path, &info.save_path_concat("removed_path"))?;
+        im::image_buffer_luma16_save(weights, &info.save_path_concat("dp_table_weights"))?;
+    }
+    im::image_buffer_save(carved.image, &info.save_path_concat("resized"))
+}
 
-        if visualize {
-            visualize = false;
+type RgbBuffer = image::ImageBuffer<image::Rgb<u8>, Vec<u8>>;
 
-            let mut image_copy = image.clone();
-            visualize_path(&mut image_copy, &path);
+pub struct Carved {
+    pub image: RgbBuffer,
+    /// image with the first removed seam drawn and its dp table weights
+    pub first_seam: Option<(RgbBuffer, GradientBuffer)>,
+}
 
-            im::image_buffer_save_png(
-                image_copy.into_rgb8(),
-                &info.save_path_concat("removed_path", image::ImageFormat::Png),
-            );
+fn check_seam_count(image: &image::DynamicImage, count: u32) -> error::Result<()> {
+    if count >= image.width() {
+        return Err(Error::InvalidParameter(format!(
+            "cannot remove {} seams from an image {} pixels wide",
+            count,
+            image.width()
+        )));
+    }
+    Ok(())
+}
 
-            im::image_buffer_luma16_save_png(
-                table.clone().to_gradient_buffer(),
-                &info.save_path_concat("dp_table_weights", image::ImageFormat::Png),
-            );
+/// removes the `count` lowest energy vertical seams one at a time, `count`
+/// must be below the image width
+pub fn carve(mut image: image::DynamicImage, count: u32, visualize: bool) -> error::Result<Carved> {
+    check_seam_count(&image, count)?;
+    let mut first_seam = None;
+    for _ in 0..count {
+        let grad = gradient_magnitude(&image);
+        let table = DPTable::from_energy_buffer(&grad);
+        let path = Path::from_dp_table(&table)?;
+
+        if visualize && first_seam.is_none() {
+            let mut image_copy = image.clone();
+            visualize_path(&mut image_copy, &path);
+            first_seam = Some((image_copy.into_rgb8(), table.into_gradient_buffer()?));
         }
-        remove_path(&mut image, path);
+        remove_path(&mut image, path)?;
     }
-
-    im::image_buffer_save_png(
-        image.into_rgb8(),
-        &info.save_path_concat("resized", image::ImageFormat::Png),
-    );
+    Ok(Carved {
+        image: image.into_rgb8(),
+        first_seam,
+    })
 }
 
-type GradientBuffer = image::ImageBuffer<image::Luma<u16>, Vec<u16>>;
+pub type GradientBuffer = image::ImageBuffer<image::Luma<u16>, Vec<u16>>;
+
+/// per pixel energy, the sum of three sobel magnitudes does not fit 16 bits
+type EnergyBuffer = image::ImageBuffer<image::Luma<u32>, Vec<u32>>;
 
-fn gradient_magnitude(image: &image::DynamicImage, visual_scale: u16) -> GradientBuffer {
+fn gradient_magnitude(image: &image::DynamicImage) -> EnergyBuffer {
     let (red, green, blue) = decompose_channels(image);
-    let r_grad = imageproc::gradients::sobel_gradients(red.as_luma8().unwrap());
-    let g_grad = imageproc::gradients::sobel_gradients(green.as_luma8().unwrap());
-    let b_grad = imageproc::gradients::sobel_gradients(blue.as_luma8().unwrap());
+    let r_grad = imageproc::gradients::sobel_gradients(&red);
+    let g_grad = imageproc::gradients::sobel_gradients(&green);
+    let b_grad = imageproc::gradients::sobel_gradients(&blue);
 
     let (w, h) = r_grad.dimensions();
-    let mut container = Vec::with_capacity((w * h) as usize);
-    for (r, g, b) in itertools::izip!(r_grad.pixels(), g_grad.pixels(), b_grad.pixels()) {
-        container.push(r[0] * visual_scale + g[0] * visual_scale + b[0] * visual_scale);
-    }
+    EnergyBuffer::from_fn(w, h, |x, y| {
+        let (r, g, b) = (
+            r_grad.get_pixel(x, y)[0] as u32,
+            g_grad.get_pixel(x, y)[0] as u32,
+            b_grad.get_pixel(x, y)[0] as u32,
+        );
+        image::Luma([r + g + b])
+    })
+}
 
-    image::ImageBuffer::from_raw(w, h, container).unwrap()
+/// energy times `visual_scale`, saturating at white
+fn visualize_energy(energy: &EnergyBuffer, visual_scale: u32) -> GradientBuffer {
+    GradientBuffer::from_fn(energy.width(), energy.height(), |x, y| {
+        let value = energy.get_pixel(x, y)[0].saturating_mul(visual_scale);
+        image::Luma([value.min(u16::MAX as u32) as u16])
+    })
 }
 
 fn decompose_channels(
     image: &image::DynamicImage,
-) -> (
-    image::DynamicImage,
-    image::DynamicImage,
-    image::DynamicImage,
-) {
+) -> (image::GrayImage, image::GrayImage, image::GrayImage) {
     let w = image.width();
     let h = image.height();
-    let mut red = image::DynamicImage::new_luma8(w, h);
-    let mut green = image::DynamicImage::new_luma8(w, h);
-    let mut blue = image::DynamicImage::new_luma8(w, h);
+    let mut red = image::GrayImage::new(w, h);
+    let mut green = image::GrayImage::new(w, h);
+    let mut blue = image::GrayImage::new(w, h);
 
     for (x, y, pixel) in image.pixels() {
-        let r = pixel[0];
-        let g = pixel[1];
-        let b = pixel[2];
-        red.put_pixel(x, y, *image::Rgba::from_slice(&[r, r, r, 255]));
-        green.put_pixel(x, y, *image::Rgba::from_slice(&[g, g, g, 255]));
-        blue.put_pixel(x, y, *image::Rgba::from_slice(&[b, b, b, 255]));
+        red.put_pixel(x, y, image::Luma([pixel[0]]));
+        green.put_pixel(x, y, image::Luma([pixel[1]]));
+        blue.put_pixel(x, y, image::Luma([pixel[2]]));
     }
 
     (red, green, blue)
 }
 
-fn remove_path(image: &mut image::DynamicImage, path: Path) {
+fn remove_path(image: &mut image::DynamicImage, path: Path) -> error::Result<()> {
     let image_buffer = image.to_rgb8();
     let (w, h) = image_buffer.dimensions();
     let container = image_buffer.into_raw();
     let mut new_pixels = vec![];
 
-    let mut path = path.indices.iter();
     let mut i = 0;
-    while let Some(&index) = path.next() {
+    for &index in path.indices.iter() {
         new_pixels.extend(&container[i..index * 3]);
         i = (index + 1) * 3;
     }
 
     new_pixels.extend(&container[i..]);
-    let ib = image::ImageBuffer::from_raw(w - 1, h, new_pixels)
-        .expect("remove_path: failed to create image_buffer");
+    let ib = image::ImageBuffer::from_raw(w - 1, h, new_pixels).ok_or_else(|| {
+        Error::InvalidParameter(format!(
+            "seam of {} pixels does not fit a {}x{} image",
+            path.indices.len(),
+            w,
+            h
+        ))
+    })?;
     *image = image::DynamicImage::ImageRgb8(ib);
+    Ok(())
 }
 
 fn visualize_path(image: &mut image::DynamicImage, path: &Path) {
@@ -123,26 +161,40 @@ fn visualize_path(image: &mut image::DynamicImage, path: &Path) {
 struct DPTable {
     width: usize,
     height: usize,
-    table: Vec<u16>,
+    /// cumulative energy, a tall image of strong edges exceeds 32 bits
+    table: Vec<u64>,
 }
 
 // TODO: horizontal
 impl DPTable {
-    fn get(&self, w: usize, h: usize) -> u16 {
+    fn get(&self, w: usize, h: usize) -> u64 {
         let i = self.width * h + w;
         self.table[i]
     }
 
-    fn set(&mut self, w: usize, h: usize, v: u16) {
+    fn set(&mut self, w: usize, h: usize, v: u64) {
         let i = self.width * h + w;
         self.table[i] = v;
     }
 
-    fn to_gradient_buffer(self) -> GradientBuffer {
-        GradientBuffer::from_raw(self.width as u32, self.height as u32, self.table).unwrap()
+    /// weights scaled to the full 16 bit range
+    fn into_gradient_buffer(self) -> error::Result<GradientBuffer> {
+        let (width, height) = (self.width, self.height);
+        let max = self.table.iter().copied().max().unwrap_or(0).max(1);
+        let weights = self
+            .table
+            .iter()
+            .map(|&v| (v * u16::MAX as u64 / max) as u16)
+            .collect();
+        GradientBuffer::from_raw(width as u32, height as u32, weights).ok_or_else(|| {
+            Error::InvalidParameter(format!(
+                "dp table does not fit a {}x{} image",
+                width, height
+            ))
+        })
     }
 
-    fn path_start_index(&self) -> usize {
+    fn path_start_index(&self) -> error::Result<usize> {
         self.table
             .iter()
             .take(self.width)
@@ -150,10 +202,10 @@ impl DPTable {
             .map(|(i, n)| (n, i))
             .min()
             .map(|(_, i)| i)
-            .unwrap()
+            .ok_or_else(|| Error::InvalidParameter("cannot carve an empty image".to_string()))
     }
 
-    fn from_gradient_buffer(gradient: &GradientBuffer) -> DPTable {
+    fn from_energy_buffer(gradient: &EnergyBuffer) -> DPTable {
         let dims = gradient.dimensions();
         let w = dims.0 as usize;
         let h = dims.1 as usize;
@@ -163,7 +215,7 @@ impl DPTable {
             table: vec![0; w * h],
         };
         // return gradient[h][w]
-        let get = |w, h| gradient.get_pixel(w as u32, h as u32)[0];
+        let get = |w, h| gradient.get_pixel(w as u32, h as u32)[0] as u64;
 
         // Initialize bottom row
         for i in 0..w {
@@ -182,7 +234,8 @@ impl DPTable {
             // special case far left and far right:
             let left = get(0, row) + (table.get(0, row + 1).min(table.get(1, row + 1)));
             table.set(0, row, left);
-            let right = get(0, row) + (table.get(w - 1, row + 1).min(table.get(w - 2, row + 1)));
+            let right =
+                get(w - 1, row) + (table.get(w - 1, row + 1).min(table.get(w - 2, row + 1)));
             table.set(w - 1, row, right);
         }
         table
@@ -195,11 +248,11 @@ struct Path {
 }
 
 impl Path {
-    fn from_dp_table(table: &DPTable) -> Path {
+    fn from_dp_table(table: &DPTable) -> error::Result<Path> {
         let mut v = Vec::with_capacity(table.height);
         let mut coords_x_y = Vec::with_capacity(table.height);
 
-        let mut col: usize = table.path_start_index();
+        let mut col: usize = table.path_start_index()?;
         v.push(col);
 
         for row in 1..table.height {
@@ -230,9 +283,33 @@ impl Path {
             coords_x_y.push((col as u32, row as u32));
         }
 
-        Path {
+        Ok(Path {
             indices: v,
             coords_x_y,
-        }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn right_edge_sums_its_own_energy() {
+        let energy =
+            EnergyBuffer::from_fn(3, 2, |x, _| image::Luma([if x == 0 { 100 } else { 0 }]));
+        let table = DPTable::from_energy_buffer(&energy);
+        assert_eq!(table.get(2, 0), 0);
+        assert_eq!(table.get(0, 0), 100);
+    }
+
+    #[test]
+    fn carves_a_textured_photo_sized_image() {
+        // strong edges in every row push the cumulative cost far past 16 bits
+        let buffer = crate::integral::tests::test_image(640, 480);
+        let carved = carve(image::DynamicImage::ImageRgb8(buffer), 2, true).unwrap();
+        assert_eq!(carved.image.dimensions(), (638, 480));
+        let (_, weights) = carved.first_seam.unwrap();
+        assert_eq!(weights.iter().copied().max(), Some(u16::MAX));
     }
 }
@@ -1,192 +1,402 @@
-use crate::im;
-use image::{GenericImage, Rgb};
+use crate::border::{self, BorderMode};
+use crate::dither::Dither;
+use crate::error;
+use crate::geometry;
+use crate::im::{self, Light};
+use crate::integral::{self, IntegralImage};
+use crate::progress;
+use crate::quantize;
+use image::Rgb;
 use std::path::PathBuf;
 
-pub fn run(target: &PathBuf, intensity_levels: u32) {
-    for (image, info) in im::open_and_setup_output(target) {
-        process_image(image, info, intensity_levels)
+/// how intensity level bins are placed
+#[derive(Copy, Clone)]
+pub enum LevelScale {
+    /// equal width bins
+    Uniform,
+    /// bins minimizing the squared error for the image histogram
+    LloydMax,
+}
+
+impl LevelScale {
+    pub const NAMES: &'static str = "uniform, lloyd-max";
+
+    pub fn from_name(name: &str) -> Option<LevelScale> {
+        match name {
+            "uniform" => Some(LevelScale::Uniform),
+            "lloyd-max" => Some(LevelScale::LloydMax),
+            _ => None,
+        }
     }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            LevelScale::Uniform => "uniform",
+            LevelScale::LloydMax => "lloyd_max",
+        }
+    }
+}
+
+pub struct Options {
+    /// level counts in `2..=256`, one output each
+    pub intensity_levels: Vec<u32>,
+    pub level_scale: LevelScale,
+    /// border handling of block and region means
+    pub border: BorderMode,
+    /// `(width, height)` of each region mean pixelation pass
+    pub region_sizes: Vec<(u32, u32)>,
+    /// dithering of the intensity level outputs
+    pub dither: Dither,
+    /// average and interpolate in linear light instead of sRGB bytes
+    pub linear: bool,
+}
+
+pub fn run(batch: &im::Batch, targets: &[PathBuf], options: &Options) -> error::Result<()> {
+    im::for_each_image(batch, targets, &results(options), |image, info| {
+        process_image(image, info, options)
+    })
 }
 
-fn process_image(image: image::DynamicImage, info: im::ImageInfo, intensity_levels: u32) {
-    im::image_buffer_save_png(
-        image_rotate_45(image.clone()),
-        &info.save_path_concat("rotate_45", image::ImageFormat::Png),
+const BLOCK_MEAN_SIZES: [u32; 3] = [3, 11, 21];
+
+/// postfixes of everything `process_image` saves
+fn results(options: &Options) -> Vec<String> {
+    let mut results = vec!["rotate_45".to_string(), "rotate_90".to_string()];
+    results.extend(options.intensity_levels.iter().map(|count| {
+        format!(
+            "intensity_levels_{count}_{}{}",
+            options.level_scale.name(),
+            options.dither.postfix()
+        )
+    }));
+    results.extend(
+        BLOCK_MEAN_SIZES
+            .iter()
+            .map(|size| format!("pixels_to_block_mean_{size}x{size}")),
     );
-    im::image_buffer_save_png(
-        image_rotate_90(image.clone()),
-        &info.save_path_concat("rotate_90", image::ImageFormat::Png),
+    results.extend(
+        options
+            .region_sizes
+            .iter()
+            .map(|(width, height)| format!("region_to_block_mean_{width}x{height}")),
     );
+    results
+}
 
-    let mut factor: u8 = 1;
-    for level in 0..intensity_levels {
-        if factor == 128 {
-            break;
-        }
-        factor *= 2;
-        im::image_buffer_save_png(
-            image_clamp_intensity_level(image.clone(), factor),
-            &info.save_path_concat(&format!("intensity_level_{level}"), image::ImageFormat::Png),
-        );
+fn process_image(
+    image: image::DynamicImage,
+    info: im::ImageInfo,
+    options: &Options,
+) -> error::Result<()> {
+    im::image_buffer_save(
+        image_rotate_45(image.clone(), options.linear)?,
+        &info.save_path_concat("rotate_45"),
+    )?;
+    im::image_buffer_save(
+        image_rotate_90(image.clone()),
+        &info.save_path_concat("rotate_90"),
+    )?;
+
+    let buffer = image.to_rgb8();
+    for &count in options.intensity_levels.iter() {
+        let leveled = intensity_levels(&buffer, count, options.level_scale, &options.dither);
+        let error = quantize::measure_error(&buffer, &leveled);
+        progress::println(format!(
+            "intensity levels: `{}` {} {}, mse {:.2}, psnr {:.2} dB",
+            info.name(),
+            count,
+            options.level_scale.name(),
+            error.mse,
+            error.psnr
+        ));
+        im::image_buffer_save(
+            leveled,
+            &info.save_path_concat(&format!(
+                "intensity_levels_{count}_{}{}",
+                options.level_scale.name(),
+                options.dither.postfix()
+            )),
+        )?;
     }
 
-    let block_mean_sizes = [3, 11, 21];
-    for block_size in block_mean_sizes {
-        im::image_buffer_save_png(
-            image_set_pixels_to_block_mean(image.clone(), block_size),
-            &info.save_path_concat(
-                &format!("pixels_to_block_mean_{block_size}x{block_size}"),
-                image::ImageFormat::Png,
+    for block_size in BLOCK_MEAN_SIZES {
+        im::image_buffer_save(
+            image_set_pixels_to_block_mean(
+                image.clone(),
+                block_size,
+                options.border,
+                options.linear,
             ),
-        );
+            &info.save_path_concat(&format!("pixels_to_block_mean_{block_size}x{block_size}")),
+        )?;
     }
 
-    let region_mean_sizes = [3, 5, 7];
-    for block_size in region_mean_sizes {
-        im::image_buffer_save_png(
-            image_set_region_to_block_mean(image.clone(), block_size),
-            &info.save_path_concat(
-                &format!("region_to_block_mean_{block_size}x{block_size}"),
-                image::ImageFormat::Png,
+    for &(block_width, block_height) in options.region_sizes.iter() {
+        im::image_buffer_save(
+            image_set_region_to_block_mean(
+                image.clone(),
+                block_width,
+                block_height,
+                options.border,
+                options.linear,
             ),
-        );
+            &info.save_path_concat(&format!(
+                "region_to_block_mean_{block_width}x{block_height}"
+            )),
+        )?;
     }
+    Ok(())
+}
+
+fn image_rotate_45(
+    image: image::DynamicImage,
+    linear: bool,
+) -> error::Result<image::ImageBuffer<Rgb<u8>, Vec<u8>>> {
+    let rotated = geometry::rotate(
+        &image.into_rgba8(),
+        45.0,
+        geometry::Interpolation::Bilinear,
+        geometry::Canvas::Expand,
+        image::Rgba([0, 0, 0, 255]),
+        linear,
+    )?;
+    Ok(image::DynamicImage::ImageRgba8(rotated).into_rgb8())
 }
 
-fn rotated_dimensions(width: u32, height: u32) -> (u32, u32) {
-    let diagonal = ((width as f64).powf(2.0) + (height as f64).powf(2.0)).sqrt();
-    let new_width = diagonal.round() as u32;
-    let new_height = new_width;
-    (new_width, new_height)
+fn image_rotate_90(image: image::DynamicImage) -> image::ImageBuffer<Rgb<u8>, Vec<u8>> {
+    geometry::Orthogonal::Rotate90.apply(&image).into_rgb8()
 }
 
-fn image_rotate_45(image: image::DynamicImage) -> image::ImageBuffer<Rgb<u8>, Vec<u8>> {
-    use imageproc::geometric_transformations::{rotate_about_center, Interpolation};
+/// per channel table from value to its level reconstruction value
+type LevelTables = [[u8; 256]; 3];
 
-    let buffer = image.into_rgb8();
-    //let new_width = ((buffer.width() as f32).abs() * 2f32.sqrt()).ceil() as u32;
-    //let new_height = ((buffer.height() as f32).abs() * 2f32.sqrt()).ceil() as u32;
-    let (new_width, new_height) = rotated_dimensions(buffer.width(), buffer.height());
-    let x_offset = (new_width - buffer.width()) / 2;
-    let y_offset = (new_height - buffer.height()) / 2;
-
-    let mut rotated_image = image::ImageBuffer::new(new_width, new_height);
-    rotated_image
-        .copy_from(&buffer, x_offset, y_offset)
-        .expect("rotate copy from");
-
-    rotate_about_center(
-        &rotated_image,
-        std::f32::consts::PI / 4.0,
-        Interpolation::Bilinear,
-        Rgb([0, 0, 0]),
-    )
+const LLOYD_MAX_ITERATIONS: usize = 64;
+
+fn level_tables(
+    buffer: &image::ImageBuffer<Rgb<u8>, Vec<u8>>,
+    count: u32,
+    scale: LevelScale,
+) -> LevelTables {
+    let mut tables = [[0u8; 256]; 3];
+    for (c, table) in tables.iter_mut().enumerate() {
+        // uniform bins of 256 / count values each, reconstructed at the centre
+        let mut bounds: Vec<f64> = (0..=count)
+            .map(|i| i as f64 * 256.0 / count as f64)
+            .collect();
+        let mut levels: Vec<f64> = bounds
+            .windows(2)
+            .map(|b| (b[0] + b[1] - 1.0) / 2.0)
+            .collect();
+
+        if let LevelScale::LloydMax = scale {
+            let mut histogram = [0u64; 256];
+            for pixel in buffer.pixels() {
+                histogram[pixel[c] as usize] += 1;
+            }
+            for _ in 0..LLOYD_MAX_ITERATIONS {
+                // decision bounds halfway between levels, levels at the bin
+                // centroids, empty bins keep their level
+                for i in 1..count as usize {
+                    bounds[i] = (levels[i - 1] + levels[i]) / 2.0 + 0.5;
+                }
+                let mut changed = false;
+                for (i, level) in levels.iter_mut().enumerate() {
+                    let lo = bounds[i].ceil().max(0.0) as usize;
+                    let hi = (bounds[i + 1].ceil() as usize).min(256);
+                    let (sum, weight) = (lo..hi).fold((0u64, 0u64), |(s, w), v| {
+                        (s + v as u64 * histogram[v], w + histogram[v])
+                    });
+                    if weight > 0 {
+                        let centroid = sum as f64 / weight as f64;
+                        changed |= (centroid - *level).abs() > 1e-3;
+                        *level = centroid;
+                    }
+                }
+                if !changed {
+                    break;
+                }
+            }
+        }
+
+        let mut bin = 0;
+        for (value, entry) in table.iter_mut().enumerate() {
+            while bin + 1 < count as usize && value as f64 >= bounds[bin + 1] {
+                bin += 1;
+            }
+            *entry = levels[bin].round().clamp(0.0, 255.0) as u8;
+        }
+    }
+    tables
 }
 
-fn image_rotate_90(image: image::DynamicImage) -> image::ImageBuffer<Rgb<u8>, Vec<u8>> {
-    image.rotate90().into_rgb8()
+/// maps every channel onto `count` levels placed by `scale`, `count` in
+/// `2..=256`
+pub fn intensity_levels(
+    buffer: &image::ImageBuffer<Rgb<u8>, Vec<u8>>,
+    count: u32,
+    scale: LevelScale,
+    dither: &Dither,
+) -> image::ImageBuffer<Rgb<u8>, Vec<u8>> {
+    let tables = level_tables(buffer, count, scale);
+    image_clamp_intensity_level(buffer, &tables, count, dither)
 }
 
 fn image_clamp_intensity_level(
-    image: image::DynamicImage,
-    factor: u8,
+    buffer: &image::ImageBuffer<Rgb<u8>, Vec<u8>>,
+    tables: &LevelTables,
+    count: u32,
+    dither: &Dither,
 ) -> image::ImageBuffer<Rgb<u8>, Vec<u8>> {
-    let mut buffer = image.into_rgb8();
-    for (_, _, pixel) in buffer.enumerate_pixels_mut() {
-        pixel[0] = pixel[0] / factor * factor;
-        pixel[1] = pixel[1] / factor * factor;
-        pixel[2] = pixel[2] / factor * factor;
+    let spread = 256.0 / count as f32;
+    let levels = dither.apply(buffer, spread, |value| {
+        let q = [0, 1, 2].map(|c| tables[c][value[c].round().clamp(0.0, 255.0) as usize]);
+        (q, q.map(|v| v as f32))
+    });
+    let mut leveled = buffer.clone();
+    for (pixel, level) in leveled.pixels_mut().zip(levels) {
+        pixel.0 = level;
     }
-    buffer
+    leveled
 }
 
 fn image_set_pixels_to_block_mean(
     image: image::DynamicImage,
     block_size: u32,
+    border: BorderMode,
+    linear: bool,
+) -> image::ImageBuffer<Rgb<u8>, Vec<u8>> {
+    integral::image_set_pixels_to_box_mean(image, block_size, block_size, border, linear)
+}
+
+/// pixelation, every `block_width` x `block_height` block is set to its mean,
+/// trailing partial blocks are averaged over the pixels inside the image in
+/// shrink mode and over the full block extended by `border` otherwise
+pub fn image_set_region_to_block_mean(
+    image: image::DynamicImage,
+    block_width: u32,
+    block_height: u32,
+    border: BorderMode,
+    linear: bool,
 ) -> image::ImageBuffer<Rgb<u8>, Vec<u8>> {
     assert!(
-        block_size % 2 == 1,
-        "block_size must be odd (eg: 1, 3, 5, 7..)"
+        block_width > 0 && block_height > 0,
+        "block sizes must be positive"
     );
-    let src_buffer = image.into_rgb8();
-    let offset = block_size / 2;
-
-    let mut buffer = src_buffer.clone();
-    for (x, y, pixel) in buffer.enumerate_pixels_mut() {
-        let x_lb = if x <= offset { 0 } else { x - offset };
-        let x_rb = (x + offset).clamp(x, src_buffer.width() - 1);
-        let y_tb = if y <= offset { 0 } else { y - offset };
-        let y_bb = (y + offset).clamp(y, src_buffer.height() - 1);
-
-        let mut color_sum: [u32; 3] = [0, 0, 0];
-        let mut area_count: u32 = 0;
-
-        for xs in x_lb..=x_rb {
-            for ys in y_tb..=y_bb {
-                let src_pixel = src_buffer.get_pixel(xs, ys);
-                color_sum[0] += src_pixel[0] as u32;
-                color_sum[1] += src_pixel[1] as u32;
-                color_sum[2] += src_pixel[2] as u32;
-                area_count += 1;
+    let buffer = image.into_rgb8();
+    if linear {
+        let linear = im::buffer_into_linear(&buffer);
+        im::buffer_from_linear(&region_mean(linear, block_width, block_height, border))
+    } else {
+        region_mean(buffer, block_width, block_height, border)
+    }
+}
+
+fn region_mean<P: Light>(
+    mut buffer: image::ImageBuffer<Rgb<P>, Vec<P>>,
+    block_width: u32,
+    block_height: u32,
+    border: BorderMode,
+) -> image::ImageBuffer<Rgb<P>, Vec<P>>
+where
+    Rgb<P>: image::Pixel<Subpixel = P>,
+{
+    let (width, height) = buffer.dimensions();
+    let x_block_count = width.div_ceil(block_width);
+    let y_block_count = height.div_ceil(block_height);
+
+    let integral = match border {
+        BorderMode::Shrink => IntegralImage::new(&buffer),
+        _ => IntegralImage::new(&border::pad(
+            &buffer,
+            0,
+            0,
+            x_block_count * block_width - width,
+            y_block_count * block_height - height,
+            border,
+        )),
+    };
+
+    for yb in 0..y_block_count {
+        for xb in 0..x_block_count {
+            let x_lb = xb * block_width;
+            let x_rb = x_lb + block_width;
+            let y_tb = yb * block_height;
+            let y_bb = y_tb + block_height;
+
+            let mean = match border {
+                BorderMode::Shrink => integral.mean(x_lb, y_tb, x_rb.min(width), y_bb.min(height)),
+                _ => integral.mean(x_lb, y_tb, x_rb, y_bb),
+            };
+            for ys in y_tb..y_bb.min(height) {
+                for xs in x_lb..x_rb.min(width) {
+                    buffer.put_pixel(xs, ys, mean);
+                }
             }
         }
-        let mean = Rgb::<u8>([
-            (color_sum[0] / area_count) as u8,
-            (color_sum[1] / area_count) as u8,
-            (color_sum[2] / area_count) as u8,
-        ]);
-
-        *pixel = mean;
     }
+
     buffer
 }
 
-fn image_set_region_to_block_mean(
-    image: image::DynamicImage,
-    block_size: u32,
-) -> image::ImageBuffer<Rgb<u8>, Vec<u8>> {
-    assert!(
-        block_size % 2 == 1,
-        "block_size must be odd (eg: 1, 3, 5, 7..)"
-    );
-    let src_buffer = image.into_rgb8();
-    let mut buffer = src_buffer.clone();
-
-    let x_block_count = buffer.width() / block_size;
-    let y_block_count = buffer.height() / block_size;
-
-    for xb in 0..x_block_count {
-        for yb in 0..y_block_count {
-            let x_lb = xb * block_size;
-            let x_rb = (x_lb + block_size).clamp(0, src_buffer.width() - 1);
-            let y_tb = yb * block_size;
-            let y_bb = (y_tb + block_size).clamp(0, src_buffer.height() - 1);
-
-            let mut color_sum: [u32; 3] = [0, 0, 0];
-            let mut area_count: u32 = 0;
-
-            for xs in x_lb..=x_rb {
-                for ys in y_tb..=y_bb {
-                    let src_pixel = src_buffer.get_pixel(xs, ys);
-                    color_sum[0] += src_pixel[0] as u32;
-                    color_sum[1] += src_pixel[1] as u32;
-                    color_sum[2] += src_pixel[2] as u32;
-                    area_count += 1;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::integral::tests::{test_image, IMAGE_SIZES, MODES};
+
+    type RgbBuffer = image::ImageBuffer<Rgb<u8>, Vec<u8>>;
+
+    /// every pixel set to the nested loop mean of the block it falls in
+    fn nested_loop_region_mean(
+        buffer: &RgbBuffer,
+        block_width: u32,
+        block_height: u32,
+        border: BorderMode,
+    ) -> RgbBuffer {
+        RgbBuffer::from_fn(buffer.width(), buffer.height(), |x, y| {
+            let (x0, y0) = (
+                x / block_width * block_width,
+                y / block_height * block_height,
+            );
+            let mut sum = [0u64; 3];
+            let mut count = 0u64;
+            for ys in y0..y0 + block_height {
+                for xs in x0..x0 + block_width {
+                    if let Some(pixel) = border.pixel(buffer, xs as i64, ys as i64) {
+                        for c in 0..3 {
+                            sum[c] += pixel[c] as u64;
+                        }
+                        count += 1;
+                    }
                 }
             }
-            let mean = Rgb::<u8>([
-                (color_sum[0] / area_count) as u8,
-                (color_sum[1] / area_count) as u8,
-                (color_sum[2] / area_count) as u8,
-            ]);
-
-            for xs in x_lb..=x_rb {
-                for ys in y_tb..=y_bb {
-                    *buffer.get_pixel_mut(xs, ys) = mean;
+            Rgb(sum.map(|s| (s / count) as u8))
+        })
+    }
+
+    #[test]
+    fn region_mean_matches_nested_loops() {
+        for border in MODES {
+            for (width, height) in IMAGE_SIZES {
+                let buffer = test_image(width, height);
+                for (block_width, block_height) in [(1, 1), (2, 2), (3, 2), (4, 7), (16, 16)] {
+                    let image = image::DynamicImage::ImageRgb8(buffer.clone());
+                    assert!(
+                        image_set_region_to_block_mean(
+                            image,
+                            block_width,
+                            block_height,
+                            border,
+                            false
+                        ) == nested_loop_region_mean(&buffer, block_width, block_height, border),
+                        "{}x{} blocks on {}x{} image with {:?} border",
+                        block_width,
+                        block_height,
+                        width,
+                        height,
+                        border
+                    );
                 }
             }
         }
     }
-
-    buffer
 }